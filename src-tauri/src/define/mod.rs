@@ -13,6 +13,7 @@ pub mod position;
 pub type NanoID = String;
 
 mod serialize;
+pub use serialize::Offset;
 const NANOID_ALPHABET: [char; 36] = [
     'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's',
     't', 'u', 'v', 'w', 'x', 'y', 'z', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9',
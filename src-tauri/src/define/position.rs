@@ -1,18 +1,17 @@
 use serde::de::{self};
-use serde::Deserializer;
 use serde::{Deserialize, Serialize};
+use serde::{Deserializer, Serializer};
 use std::mem::{size_of, size_of_val};
 use std::{fmt, vec};
 
-use super::serialize::{EncodeBinary, Offset};
-use crate::racekeys::get_race_key_bytes;
+use super::serialize::{ByteReader, EncodeBinary, Offset, REGISTRY_VERSION};
+use crate::racekeys::{get_race_key_bytes, get_race_key_from_byte};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Position {
     pub sex: Sex,
     pub race: String,
-    #[serde(deserialize_with = "deserialize_vec_or_string")]
-    pub event: Vec<String>,
+    pub event: EventSpec,
 
     pub scale: f32,
     pub extra: Extra,
@@ -23,6 +22,75 @@ pub struct Position {
     pub schlong: i8,
 }
 
+// Placeholder events which do not refer to an actual animation
+pub const CONTROL_EVENTS: [&str; 2] = ["__BLANK__", "__DEFAULT__"];
+
+// A single animation event or a sequence of events chained one after another
+// Serialized as a list of events in either case
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventSpec {
+    Single(String),
+    Chain(Vec<String>),
+}
+
+impl EventSpec {
+    pub fn first(&self) -> Option<&String> {
+        self.as_slice().first()
+    }
+
+    pub fn as_slice(&self) -> &[String] {
+        match self {
+            EventSpec::Single(event) => std::slice::from_ref(event),
+            EventSpec::Chain(events) => events,
+        }
+    }
+
+    // true if none of the events refer to an actual animation
+    pub fn is_control(&self) -> bool {
+        self.as_slice()
+            .iter()
+            .all(|event| CONTROL_EVENTS.contains(&event.as_str()))
+    }
+
+    pub fn is_chain(&self) -> bool {
+        matches!(self, EventSpec::Chain(events) if !events.is_empty())
+    }
+}
+
+impl From<Vec<String>> for EventSpec {
+    fn from(mut value: Vec<String>) -> Self {
+        if value.len() == 1 {
+            EventSpec::Single(value.remove(0))
+        } else {
+            EventSpec::Chain(value)
+        }
+    }
+}
+
+impl Default for EventSpec {
+    fn default() -> Self {
+        EventSpec::Chain(vec![])
+    }
+}
+
+impl Serialize for EventSpec {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for EventSpec {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_vec_or_string(deserializer).map(EventSpec::from)
+    }
+}
+
 struct DeserializeVecOrString;
 impl<'de> de::Visitor<'de> for DeserializeVecOrString {
     type Value = Vec<String>;
@@ -63,6 +131,72 @@ pub struct Sex {
     pub futa: bool,
 }
 
+// Naming convention by which an event name encodes the sex of the actor playing it
+// Patterns may use * as a wildcard for any number of characters, e.g. "*_M" for all events ending in _M
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SexNaming {
+    pub male: Vec<String>,
+    pub female: Vec<String>,
+    pub case_sensitive: bool,
+}
+
+impl SexNaming {
+    // Sex expected by the given event name, None if the name does not follow the convention
+    pub fn expected_sex(&self, event: &str) -> Option<Sex> {
+        let matches = |patterns: &Vec<String>| {
+            patterns.iter().any(|pattern| match self.case_sensitive {
+                true => glob_match(pattern, event),
+                false => glob_match(&pattern.to_lowercase(), &event.to_lowercase()),
+            })
+        };
+        match (matches(&self.male), matches(&self.female)) {
+            (true, false) => Some(Sex {
+                male: true,
+                female: false,
+                futa: false,
+            }),
+            (false, true) => Some(Sex {
+                male: false,
+                female: true,
+                futa: false,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Default for SexNaming {
+    fn default() -> Self {
+        Self {
+            male: vec!["*_M".into(), "*_M_*".into()],
+            female: vec!["*_F".into(), "*_F_*".into()],
+            case_sensitive: false,
+        }
+    }
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    if !text.starts_with(first) {
+        return false;
+    }
+    let mut rest = &text[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.ends_with(last)
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Extra {
     pub submissive: bool,
@@ -131,6 +265,59 @@ impl Position {
         }
     }
 
+    // Position holding the data shared by all stages, as written by write_byte_meta
+    pub fn read_byte_meta(reader: &mut ByteReader) -> Result<Self, String> {
+        let mut ret = Position::default();
+        let race = reader.read_u8()?;
+        ret.race = get_race_key_from_byte(race).ok_or(format!("Unknown race key {}", race))?;
+        let sex = reader.read_u8()?;
+        ret.sex = Sex {
+            male: sex & 1 != 0,
+            female: sex & 2 != 0,
+            futa: sex & 4 != 0,
+        };
+        ret.scale = reader.read_f32()?;
+        let extra = reader.read_u8()?;
+        ret.extra.submissive = extra & 1 != 0;
+        ret.extra.vampire = extra & 4 != 0;
+        ret.extra.dead = extra & 8 != 0;
+        let count = reader.read_u64()?;
+        for _ in 0..count {
+            ret.extra.custom.push(reader.read_string()?);
+        }
+
+        Ok(ret)
+    }
+
+    // Read the stage specific data written by write_byte, only the first event of a chain is stored
+    pub fn read_byte(&mut self, reader: &mut ByteReader, version: u8) -> Result<(), String> {
+        self.event = EventSpec::Single(reader.read_string()?);
+        self.extra.climax = reader.read_u8()? != 0;
+        self.offset = Offset::read_byte(reader)?;
+        self.strip_data = Stripping::read_byte(reader)?;
+        if version >= 3 {
+            self.schlong = reader.read_u8()? as i8;
+        }
+
+        Ok(())
+    }
+
+    pub fn export_offset(&self) -> serde_yaml::Mapping {
+        let mut transform = serde_yaml::Mapping::new();
+        transform.insert(
+            "Location".into(),
+            serde_yaml::Value::Sequence(vec![
+                (self.offset.x as f64).into(),
+                (self.offset.y as f64).into(),
+                (self.offset.z as f64).into(),
+            ]),
+        );
+        transform.insert("Rotation".into(), (self.offset.r as f64).into());
+        let mut ret = serde_yaml::Mapping::new();
+        ret.insert("transform".into(), transform.into());
+        ret
+    }
+
     pub fn import_offset(&mut self, yaml_obj: &serde_yaml::Mapping) -> Result<(), String> {
         let loc = yaml_obj[&"Location".into()]
             .as_sequence()
@@ -156,6 +343,7 @@ impl Position {
         self.offset.y = loc[1] as f32;
         self.offset.z = loc[2] as f32;
         self.offset.r = rot as f32;
+        self.offset.normalize();
 
         Ok(())
     }
@@ -163,8 +351,8 @@ impl Position {
 
 impl EncodeBinary for Position {
     fn get_byte_size(&self) -> usize {
-        size_of_val(&self.event[0].len())
-            + size_of_val(&self.event[0])
+        size_of::<usize>()
+            + size_of::<String>()
             + self.offset.get_byte_size()
             + self.strip_data.get_byte_size()
             + size_of_val(&self.extra.climax)
@@ -172,13 +360,21 @@ impl EncodeBinary for Position {
     }
 
     fn write_byte(&self, buf: &mut Vec<u8>) -> () {
+        self.write_byte_version(buf, REGISTRY_VERSION)
+    }
+
+    fn write_byte_version(&self, buf: &mut Vec<u8>, version: u8) -> () {
         // Only save initial event, all others are called by Havok
-        buf.extend_from_slice(&(self.event[0].len() as u64).to_be_bytes());
-        buf.extend_from_slice(self.event[0].as_bytes());
+        let event = self.event.first().expect("Position missing event");
+        buf.extend_from_slice(&(event.len() as u64).to_be_bytes());
+        buf.extend_from_slice(event.as_bytes());
         buf.push(self.extra.climax as u8);
         self.offset.write_byte(buf);
         self.strip_data.write_byte(buf);
-        buf.push(self.schlong as u8);
+        // schlong
+        if version >= 3 {
+            buf.push(self.schlong as u8);
+        }
     }
 }
 
@@ -198,6 +394,28 @@ impl Default for Position {
     }
 }
 
+impl Stripping {
+    pub fn read_byte(reader: &mut ByteReader) -> Result<Self, String> {
+        let byte = reader.read_u8()?;
+        let mut ret = Self {
+            default: false,
+            ..Default::default()
+        };
+        match byte {
+            0x80 => ret.default = true,
+            u8::MAX => ret.everything = true,
+            u8::MIN => ret.nothing = true,
+            _ => {
+                ret.helmet = byte & 1 != 0;
+                ret.gloves = byte & 2 != 0;
+                ret.boots = byte & 4 != 0;
+            }
+        }
+
+        Ok(ret)
+    }
+}
+
 impl EncodeBinary for Stripping {
     fn get_byte_size(&self) -> usize {
         size_of::<u8>()
@@ -238,3 +456,30 @@ impl Default for Sex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_event_serializes_as_list() {
+        let event = EventSpec::Single("Kiss_A1_S1".into());
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, r#"["Kiss_A1_S1"]"#);
+        assert_eq!(serde_json::from_str::<EventSpec>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn chained_events_serialize_as_list() {
+        let event = EventSpec::Chain(vec!["Kiss_A1_S1".into(), "Kiss_A1_S2".into()]);
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, r#"["Kiss_A1_S1","Kiss_A1_S2"]"#);
+        assert_eq!(serde_json::from_str::<EventSpec>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn plain_string_reads_as_single_event() {
+        let event: EventSpec = serde_json::from_str(r#""Kiss_A1_S1""#).unwrap();
+        assert_eq!(event, EventSpec::Single("Kiss_A1_S1".into()));
+    }
+}
@@ -1,30 +1,40 @@
-use log::info;
+use log::{info, warn};
 use nanoid::nanoid;
-use serde::{Deserialize, Serialize};
+use serde::de::{self, DeserializeSeed, MapAccess, Visitor};
+use serde::ser::{self, SerializeMap};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
-    collections::{HashMap, HashSet},
-    fs,
-    io::{BufReader, BufWriter, ErrorKind, Write},
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap, HashSet},
+    ffi::OsStr,
+    fmt, fs,
+    hash::{Hash, Hasher},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
     mem::size_of,
-    path::PathBuf,
+    path::{Path, PathBuf},
     vec,
 };
 use tauri::api::dialog::blocking::FileDialogBuilder;
 
 use crate::{
-    define::serialize::{make_fnis_lines, map_race_to_folder},
-    racekeys::map_legacy_to_racekey,
+    define::serialize::{
+        make_fnis_lines, map_race_to_folder, FNIS_OPTIONS, MAX_REGISTRY_VERSION,
+        MIN_REGISTRY_VERSION, REGISTRY_VERSION,
+    },
+    racekeys::{
+        default_offset_for, get_race_key_bytes, get_race_keys_string, map_deprecated_race,
+        map_legacy_to_racekey, FUTA_RACES,
+    },
 };
 
 use super::{
-    position::Sex,
-    scene::{Node, Scene},
-    serialize::EncodeBinary,
+    position::{EventSpec, Position, Sex, SexNaming, CONTROL_EVENTS},
+    scene::Scene,
+    serialize::{ByteReader, EncodeBinary, Offset, OffsetConvention},
     stage::Stage,
-    NanoID, NANOID_ALPHABET, PREFIX_HASH_LEN,
+    NanoID, NANOID_ALPHABET, NANOID_LENGTH, PREFIX_HASH_LEN,
 };
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Project {
     #[serde(skip)]
     pub pack_path: PathBuf,
@@ -33,6 +43,437 @@ pub struct Project {
     pub pack_author: String,
     pub prefix_hash: String,
     pub scenes: HashMap<NanoID, Scene>,
+    #[serde(default = "default_race")]
+    pub default_race: String,
+    // races which may play a futa position without being reported
+    #[serde(default = "default_futa_races")]
+    pub futa_races: Vec<String>,
+    // naming convention the events are checked against, packs are free to name their events by default
+    #[serde(default)]
+    pub sex_naming: Option<SexNaming>,
+
+    // content hash of every scene as of the last load or save
+    #[serde(skip)]
+    saved_hashes: HashMap<NanoID, u64>,
+}
+
+fn default_race() -> String {
+    "Human".into()
+}
+
+fn default_futa_races() -> Vec<String> {
+    FUTA_RACES.iter().map(|race| race.to_string()).collect()
+}
+
+// key of the layout version in offset files, cannot collide with a scene id as those are NANOID_LENGTH long
+const OFFSET_VERSION_KEY: &str = "version";
+pub const OFFSET_VERSION: u64 = 1;
+// key holding the scenes of an offset file grouped by stage tag, see export_offset_grouped
+const OFFSET_GROUPED_KEY: &str = "by_tag";
+const OFFSET_UNTAGGED: &str = "(untagged)";
+
+// Largest number of scenes a single registry file may hold
+// The registry stores the count as u64 and SexLab does not document a limit of its own, this is a conservative bound
+// chosen by the builder rather than one imposed by the game. Larger packs should be split into multiple projects
+pub const MAX_SCENES: usize = u16::MAX as usize;
+
+const PORTABLE_FORMAT: &str = "slsb-portable-1";
+
+// default name of the file holding pack metadata next to a slal file
+pub const SLAL_INFO_FILE: &str = "info.json";
+
+// Self contained project for sharing, tagged with a format marker to distinguish it from a working project file
+#[derive(Debug, Serialize, Deserialize)]
+struct PortableProject<P> {
+    format: String,
+    project: P,
+}
+
+struct CanonicalProject<'a>(&'a Project);
+
+// Serializes the entries in the given order, independent of how serde_json orders its maps
+struct OrderedMap<V>(Vec<(String, V)>);
+
+impl<V: Serialize> Serialize for OrderedMap<V> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_keys(value)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(sort_keys).collect())
+        }
+        _ => value,
+    }
+}
+
+// Hash of the scene content independent of the iteration order of its graph
+fn scene_hash(scene: &Scene) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_value(scene)
+        .map(sort_keys)
+        .map(|value| value.to_string())
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Serialize for CanonicalProject<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut scenes: Vec<&Scene> = self.0.scenes.values().collect();
+        scenes.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.id.cmp(&b.id)));
+        let mut ordered = vec![];
+        for scene in scenes {
+            let mut scene = scene.clone();
+            scene.sort_stages_by_graph();
+            ordered.push((
+                scene.id.clone(),
+                sort_keys(serde_json::to_value(&scene).map_err(ser::Error::custom)?),
+            ));
+        }
+
+        let mut fields = match serde_json::to_value(self.0).map_err(ser::Error::custom)? {
+            serde_json::Value::Object(map) => map.into_iter().collect::<Vec<_>>(),
+            _ => vec![],
+        };
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut map = serializer.serialize_map(Some(fields.len()))?;
+        for (key, value) in fields {
+            match key.as_str() {
+                "scenes" => map.serialize_entry(&key, &OrderedMap(std::mem::take(&mut ordered)))?,
+                _ => map.serialize_entry(&key, &sort_keys(value))?,
+            }
+        }
+        map.end()
+    }
+}
+
+// Deserializes a project while reporting every scene read, scenes are streamed one by one and all other
+// fields are collected and deserialized as usual once the object has been read
+struct ProjectSeed<'a, F> {
+    progress: &'a mut F,
+}
+
+struct ScenesSeed<'a, F> {
+    progress: &'a mut F,
+}
+
+impl<'de, 'a, F: FnMut(usize)> DeserializeSeed<'de> for ProjectSeed<'a, F> {
+    type Value = Project;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a, F: FnMut(usize)> Visitor<'de> for ProjectSeed<'a, F> {
+    type Value = Project;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a project")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut rest = serde_json::Map::new();
+        let mut scenes = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "scenes" {
+                scenes = Some(map.next_value_seed(ScenesSeed {
+                    progress: &mut *self.progress,
+                })?);
+                rest.insert(key, serde_json::Value::Object(Default::default()));
+            } else {
+                rest.insert(key, map.next_value()?);
+            }
+        }
+        let mut project: Project =
+            serde_json::from_value(serde_json::Value::Object(rest)).map_err(de::Error::custom)?;
+        project.scenes = scenes.unwrap_or_default();
+        Ok(project)
+    }
+}
+
+impl<'de, 'a, F: FnMut(usize)> DeserializeSeed<'de> for ScenesSeed<'a, F> {
+    type Value = HashMap<NanoID, Scene>;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 'a, F: FnMut(usize)> Visitor<'de> for ScenesSeed<'a, F> {
+    type Value = HashMap<NanoID, Scene>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of scenes")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut ret = HashMap::new();
+        while let Some((id, scene)) = map.next_entry::<NanoID, Scene>()? {
+            ret.insert(id, scene);
+            (self.progress)(ret.len());
+        }
+        Ok(ret)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    // write a build-log.json summarizing the build next to the exported files
+    pub write_log: bool,
+    // version of the registry file, lower versions support older SexLab builds and higher ones add stage data
+    pub registry_version: u8,
+    // fail instead of skipping scenes with warnings
+    pub strict: bool,
+    // prepended to every scene name in the registry file, the project itself is left untouched
+    pub scene_name_prefix: String,
+    // write FNIS lists for races which are used in the project but have no animations registered to them
+    pub empty_race_files: bool,
+    // event to register in place of __DEFAULT__, by default these positions are not registered at all
+    pub default_event: Option<String>,
+    // maximum number of lines in a single FNIS list, larger lists are split into numbered packs:
+    // FNIS_{pack}_List.txt, FNIS_{pack}2_List.txt, ... each in its own animations\{pack}n folder
+    // the animation files of a chunk have to be placed in the folder of that chunk, see hkx_manifest
+    pub split_threshold: Option<usize>,
+    // axis convention of exported offset files, the registry always uses the native convention
+    pub offset_convention: OffsetConvention,
+    // separate folders for the SKSE registry and the FNIS meshes, both default to the folder passed to build
+    pub registry_root: Option<PathBuf>,
+    pub meshes_root: Option<PathBuf>,
+}
+
+impl BuildOptions {
+    fn registry_dir<'a>(&'a self, root_dir: &'a Path) -> &'a Path {
+        self.registry_root.as_deref().unwrap_or(root_dir)
+    }
+
+    fn meshes_dir<'a>(&'a self, root_dir: &'a Path) -> &'a Path {
+        self.meshes_root.as_deref().unwrap_or(root_dir)
+    }
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        Self {
+            write_log: Default::default(),
+            registry_version: REGISTRY_VERSION,
+            strict: Default::default(),
+            scene_name_prefix: Default::default(),
+            empty_race_files: Default::default(),
+            default_event: None,
+            split_threshold: None,
+            offset_convention: Default::default(),
+            registry_root: None,
+            meshes_root: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct BuildLog {
+    pub scenes_exported: Vec<NanoID>,
+    pub scenes_skipped: Vec<NanoID>,
+    pub races: BTreeMap<String, usize>, // map<RaceKey, LineCount>
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    // scenes with any of these tags do not have climax assigned to their last stage
+    pub skip_climax_tags: Vec<String>,
+    // name of a json file next to the slal file to read the pack author from, the author is left unset if it is missing
+    pub info_file: Option<String>,
+    // axis convention of imported offset files
+    pub offset_convention: OffsetConvention,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            skip_climax_tags: vec!["massage".into(), "idle".into(), "cuddle".into()],
+            info_file: Some(SLAL_INFO_FILE.into()),
+            offset_convention: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct HealthReport {
+    pub passed: bool,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct CompatReport {
+    pub races: BTreeSet<String>,
+    pub requirements: BTreeSet<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TableFormat {
+    Markdown,
+    BBCode,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IdKind {
+    Scene,
+    Stage,
+    GraphNode,
+}
+
+fn make_fnis_list_path(root_dir: &Path, racekey: &str, pack_name: &str) -> Option<PathBuf> {
+    let target_folder = map_race_to_folder(racekey).ok()?;
+    let path = root_dir.join(format!(
+        "meshes\\actors\\{}\\animations\\{}",
+        target_folder, pack_name
+    ));
+    let crt = &target_folder[target_folder
+        .find('\\')
+        .and_then(|w| Some(w + 1))
+        .unwrap_or(0)..];
+    let file_name = match crt {
+        "character" => format!("FNIS_{}_List.txt", pack_name),
+        "canine" => match racekey {
+            "Canine" => format!("FNIS_{}_canine_List.txt", pack_name),
+            "Dog" => format!("FNIS_{}_dog_List.txt", pack_name),
+            _ => format!("FNIS_{}_wolf_List.txt", pack_name),
+        },
+        _ => format!("FNIS_{}_{}_List.txt", pack_name, crt),
+    };
+
+    Some(path.join(file_name))
+}
+
+// Pack name of the n-th chunk of a split FNIS list, FNIS reads one list per mod folder so every chunk
+// after the first is written as its own numbered mod: {pack}, {pack}2, {pack}3, ...
+fn fnis_chunk_name(pack_name: &str, n: usize) -> String {
+    match n {
+        0 => pack_name.into(),
+        _ => format!("{}{}", pack_name, n + 1),
+    }
+}
+
+// Split lines into chunks of at most threshold lines, a chain of animations (s followed by +) is never split
+fn split_fnis_lines(lines: &[String], threshold: Option<usize>) -> Vec<&[String]> {
+    let threshold = match threshold {
+        Some(n) if n > 0 && lines.len() > n => n,
+        _ => return vec![lines],
+    };
+    let mut ret = vec![];
+    let mut start = 0;
+    let mut chain_start = 0;
+    for (i, line) in lines.iter().enumerate() {
+        if !line.starts_with('+') {
+            chain_start = i;
+        }
+        if i - start >= threshold && chain_start > start {
+            ret.push(&lines[start..chain_start]);
+            start = chain_start;
+        }
+    }
+    ret.push(&lines[start..]);
+
+    ret
+}
+
+// Convert the offsets of a list of positions as written by Stage::export_offset
+fn convert_offsets(positions: &mut serde_yaml::Sequence, f: impl Fn(&Offset) -> Offset) -> () {
+    for value in positions {
+        let transform = value
+            .as_mapping()
+            .and_then(|obj| obj.get(&"transform".into()))
+            .and_then(|obj| obj.as_mapping());
+        if let Some(transform) = transform {
+            let mut position = Position::default();
+            if position.import_offset(transform).is_ok() {
+                position.offset = f(&position.offset);
+                *value = position.export_offset().into();
+            }
+        }
+    }
+}
+
+fn export_stage_offset(stage: &Stage, convention: OffsetConvention) -> serde_yaml::Value {
+    let mut positions = stage.export_offset();
+    if !convention.is_identity() {
+        convert_offsets(&mut positions, |offset| convention.export(offset));
+    }
+    positions.into()
+}
+
+// Merge the tag groups of an offset file written by export_offset_grouped back into the flat scene/stage form
+fn ungroup_offset(mut offsetfile: serde_yaml::Mapping) -> Result<serde_yaml::Mapping, String> {
+    let groups = match offsetfile.remove(&OFFSET_GROUPED_KEY.into()) {
+        Some(serde_yaml::Value::Mapping(groups)) => groups,
+        Some(_) => return Err("Not a valid offset file, expected mapping for tag groups".into()),
+        None => return Ok(offsetfile),
+    };
+    for (tag, scenes) in groups {
+        let scenes = scenes.as_mapping().ok_or(format!(
+            "Expected mapping in tag group {}",
+            tag.as_str().unwrap_or_default()
+        ))?;
+        for (scene_id, stages) in scenes {
+            let stages = stages.as_mapping().ok_or(format!(
+                "Expected mapping in scene {}",
+                scene_id.as_str().unwrap_or_default()
+            ))?;
+            let entry = offsetfile
+                .entry(scene_id.clone())
+                .or_insert_with(|| serde_yaml::Mapping::new().into());
+            if let serde_yaml::Value::Mapping(flat) = entry {
+                for (stage_id, offset) in stages {
+                    flat.insert(stage_id.clone(), offset.clone());
+                }
+            }
+        }
+    }
+
+    Ok(offsetfile)
+}
+
+fn read_info_author(path: &Path) -> Result<Option<String>, String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let info: serde_json::Value =
+        serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
+    Ok(info["author"].as_str().map(|author| author.to_string()))
 }
 
 impl Project {
@@ -44,6 +485,10 @@ impl Project {
             pack_author: "Unknown".into(),
             prefix_hash: nanoid!(PREFIX_HASH_LEN, &NANOID_ALPHABET),
             scenes: HashMap::new(),
+            default_race: default_race(),
+            futa_races: default_futa_races(),
+            sex_naming: None,
+            saved_hashes: HashMap::new(),
         }
     }
 
@@ -80,6 +525,28 @@ impl Project {
         self.scenes.get_mut(id)
     }
 
+    pub fn set_default_race(&mut self, race: String) -> Result<(), String> {
+        if get_race_key_bytes(&race).is_none() {
+            return Err(format!("Unrecognized race: {}", race));
+        }
+        self.default_race = race;
+        Ok(())
+    }
+
+    pub fn make_position(&self) -> Position {
+        let mut ret = Position::default();
+        ret.race = self.default_race.clone();
+        ret
+    }
+
+    pub fn make_stage(&self, count: usize) -> Stage {
+        let mut ret = Stage::from_count(count);
+        for position in &mut ret.positions {
+            position.race = self.default_race.clone();
+        }
+        ret
+    }
+
     pub fn get_stage(&self, id: &NanoID) -> Option<&Stage> {
         for (_, scene) in &self.scenes {
             let stage = scene.get_stage(id);
@@ -106,12 +573,148 @@ impl Project {
     }
 
     pub fn from_file(file: std::fs::File) -> Result<Project, String> {
-        let project: Project =
-            serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
+        Project::from_file_with_progress(file, |_| {})
+    }
+
+    // Load a project, calling progress with the number of scenes read so far after every scene
+    pub fn from_file_with_progress<F>(file: std::fs::File, progress: F) -> Result<Project, String>
+    where
+        F: FnMut(usize),
+    {
+        Project::from_reader_with_progress(BufReader::new(file), progress)
+    }
+
+    fn from_reader_with_progress<R, F>(reader: R, mut progress: F) -> Result<Project, String>
+    where
+        R: Read,
+        F: FnMut(usize),
+    {
+        let mut deserializer = serde_json::Deserializer::from_reader(reader);
+        let mut project = ProjectSeed {
+            progress: &mut progress,
+        }
+        .deserialize(&mut deserializer)
+        .map_err(|e| e.to_string())?;
+        deserializer.end().map_err(|e| e.to_string())?;
+        project.prepare_loaded();
         println!("Loaded project {}", project.pack_name);
         Ok(project)
     }
 
+    // Repairs applied to every project read from disk, independent of the format it was stored in
+    fn prepare_loaded(&mut self) -> () {
+        self.migrate_races();
+        self.normalize_rotations();
+        self.dedup_stage_ids();
+        self.rebuild_graphs();
+        self.snapshot_hashes();
+    }
+
+    fn snapshot_hashes(&mut self) -> () {
+        self.saved_hashes = self
+            .scenes
+            .iter()
+            .map(|(id, scene)| (id.clone(), scene_hash(scene)))
+            .collect();
+    }
+
+    // Ids of all scenes which were added, edited or removed since the project was last loaded or saved
+    pub fn changed_scenes_since_save(&self) -> Vec<NanoID> {
+        let mut ret: Vec<NanoID> = self
+            .scenes
+            .iter()
+            .filter(|(id, scene)| self.saved_hashes.get(*id) != Some(&scene_hash(scene)))
+            .map(|(id, _)| id.clone())
+            .chain(
+                self.saved_hashes
+                    .keys()
+                    .filter(|id| !self.scenes.contains_key(*id))
+                    .cloned(),
+            )
+            .collect();
+        ret.sort();
+
+        ret
+    }
+
+    // Replace race names used by older versions with their current RaceKey, returning the number of changed positions
+    pub fn migrate_races(&mut self) -> usize {
+        let mut count = 0;
+        for (_, scene) in &mut self.scenes {
+            for stage in &mut scene.stages {
+                for position in &mut stage.positions {
+                    if let Some(race) = map_deprecated_race(&position.race) {
+                        info!(
+                            "Scene {}: Migrating race {} to {}",
+                            scene.id, position.race, race
+                        );
+                        position.race = race.into();
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        count
+    }
+
+    // Give every stage whose id is already used within its scene a new id, returning the number of changed stages
+    pub fn dedup_stage_ids(&mut self) -> usize {
+        self.scenes
+            .values_mut()
+            .map(|scene| scene.dedup_stage_ids())
+            .sum()
+    }
+
+    // Replace the graph of every scene with missing nodes or dangling edges with a linear chain in stage order,
+    // returning the number of rebuilt scenes. A missing root alone is not repaired, the editor saves scenes without one
+    pub fn rebuild_graphs(&mut self) -> usize {
+        let mut count = 0;
+        for (_, scene) in &mut self.scenes {
+            if let Err(e) = scene.validate_nodes() {
+                info!("Scene {}: Rebuilding graph ({})", scene.id, e);
+                scene.make_linear_graph();
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    // Map every rotation into [0, 360), returning the number of changed positions
+    pub fn normalize_rotations(&mut self) -> usize {
+        let mut count = 0;
+        for (_, scene) in &mut self.scenes {
+            for stage in &mut scene.stages {
+                for position in &mut stage.positions {
+                    if position.offset.r != position.offset.normalized_rotation() {
+                        position.offset.normalize();
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        count
+    }
+
+    // Set the sex of all creature positions, for packs whose creatures do not distinguish between genders
+    pub fn normalize_creature_sex(&mut self, sex: Sex) -> usize {
+        let mut count = 0;
+        for (_, scene) in &mut self.scenes {
+            for stage in &mut scene.stages {
+                for position in &mut stage.positions {
+                    if position.race != "Human" {
+                        position.sex = sex.clone();
+                        count += 1;
+                    }
+                }
+            }
+        }
+
+        count
+    }
+
     pub fn save_project(&mut self, save_as: bool) -> Result<(), String> {
         let path = if save_as || !self.pack_path.exists() || self.pack_path.is_dir() {
             let f = FileDialogBuilder::new()
@@ -134,10 +737,146 @@ impl Project {
     pub fn write(&mut self, path: PathBuf) -> Result<(), String> {
         let file = fs::File::create(&path).map_err(|e| e.to_string())?;
         serde_json::to_writer(file, self).map_err(|e| e.to_string())?;
+        self.snapshot_hashes();
         println!("Saved project {}", self.pack_name);
         Ok(())
     }
 
+    pub fn export_canonical(&self) -> Result<(), String> {
+        let path = FileDialogBuilder::new()
+            .set_file_name(&self.pack_name)
+            .add_filter("SL Project File", vec!["slsb.json"].as_slice())
+            .save_file()
+            .ok_or("No path to export project to".to_string())?;
+        self.write_canonical(path)
+    }
+
+    // Pretty printed project with all keys sorted, scenes ordered by name and stages in graph order
+    // Two projects which only differ in the order their scenes and stages were created in produce identical files
+    pub fn write_canonical(&self, path: PathBuf) -> Result<(), String> {
+        let file = fs::File::create(&path).map_err(|e| e.to_string())?;
+        let mut file = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut file, &CanonicalProject(self))
+            .map_err(|e| e.to_string())?;
+        writeln!(file).map_err(|e| e.to_string())?;
+        println!("Saved canonical project {}", self.pack_name);
+        Ok(())
+    }
+
+    pub fn load_archive(&mut self) -> Result<(), String> {
+        let path = FileDialogBuilder::new()
+            .add_filter("Zip Archive", vec!["zip"].as_slice())
+            .pick_file()
+            .ok_or("No path to import archive from".to_string())?;
+        let value = Project::import_archive(&path)?;
+
+        // the archive is not a working file, saving asks for a new location
+        *self = value;
+
+        Ok(())
+    }
+
+    // Open a zip archive of a pack, using the project file if it is included and the registry file otherwise
+    pub fn import_archive(path: &Path) -> Result<Project, String> {
+        let file = fs::File::open(path).map_err(|e| e.to_string())?;
+        let mut archive = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+        let names: Vec<String> = archive.file_names().map(|name| name.to_string()).collect();
+        if let Some(name) = names.iter().find(|name| name.ends_with(".slsb.json")) {
+            let entry = archive.by_name(name).map_err(|e| e.to_string())?;
+            let mut project = Project::from_reader_with_progress(BufReader::new(entry), |_| {})?;
+            project.set_project_name_from_path(&PathBuf::from(name));
+            return Ok(project);
+        }
+        let name = names
+            .iter()
+            .find(|name| name.ends_with(".slr"))
+            .ok_or(format!(
+                "{} contains no project or registry file",
+                path.display()
+            ))?;
+        let mut entry = archive.by_name(name).map_err(|e| e.to_string())?;
+        let mut buf = vec![];
+        entry.read_to_end(&mut buf).map_err(|e| e.to_string())?;
+        warn!(
+            "{} contains no project file, reading registry {} instead. Chained events and the graph layout are not restored",
+            path.display(),
+            name
+        );
+
+        Project::from_registry_bytes(&buf)
+    }
+
+    // Decode a registry file as written by build
+    pub fn from_registry_bytes(buf: &[u8]) -> Result<Project, String> {
+        let mut reader = ByteReader::new(buf);
+        let version = reader.read_u8()?;
+        if !(MIN_REGISTRY_VERSION..=MAX_REGISTRY_VERSION).contains(&version) {
+            return Err(format!("Unsupported registry version {}", version));
+        }
+        let mut ret = Project::new();
+        ret.pack_name = reader.read_string()?;
+        ret.pack_author = reader.read_string()?;
+        ret.prefix_hash = reader.read_str(PREFIX_HASH_LEN)?;
+        // registry files of older versions count scenes which were skipped during the build, so also stop once all data is read
+        let count = reader.read_u64()?;
+        for _ in 0..count {
+            if reader.is_empty() {
+                break;
+            }
+            let scene = Scene::read_byte(&mut reader, version)?;
+            ret.scenes.insert(scene.id.clone(), scene);
+        }
+        ret.rebuild_graphs();
+
+        Ok(ret)
+    }
+
+    pub fn export_portable(&self) -> Result<(), String> {
+        let path = FileDialogBuilder::new()
+            .set_file_name(&self.pack_name)
+            .add_filter("SL Portable Project", vec!["json"].as_slice())
+            .save_file()
+            .ok_or("No path to export project to".to_string())?;
+        self.write_portable(path)
+    }
+
+    pub fn write_portable(&self, path: PathBuf) -> Result<(), String> {
+        let file = fs::File::create(&path).map_err(|e| e.to_string())?;
+        let portable = PortableProject {
+            format: PORTABLE_FORMAT.into(),
+            project: self,
+        };
+        serde_json::to_writer(file, &portable).map_err(|e| e.to_string())?;
+        println!("Exported portable project {}", self.pack_name);
+        Ok(())
+    }
+
+    pub fn import_portable(&mut self) -> Result<(), String> {
+        let path = FileDialogBuilder::new()
+            .add_filter("SL Portable Project", vec!["json"].as_slice())
+            .pick_file()
+            .ok_or("No path to import project from".to_string())?;
+        let file = fs::File::open(&path).map_err(|e| e.to_string())?;
+        let value = Project::from_portable(file)?;
+
+        // the portable file is not a working file, saving asks for a new location
+        *self = value;
+
+        Ok(())
+    }
+
+    pub fn from_portable(file: std::fs::File) -> Result<Project, String> {
+        let portable: PortableProject<Project> =
+            serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
+        if portable.format != PORTABLE_FORMAT {
+            return Err(format!("Unrecognized portable format: {}", portable.format));
+        }
+        let mut project = portable.project;
+        project.prepare_loaded();
+        println!("Loaded portable project {}", project.pack_name);
+        Ok(project)
+    }
+
     pub fn load_slal(&mut self) -> Result<(), String> {
         let path = FileDialogBuilder::new()
             .add_filter("SLAL File", vec!["json"].as_slice())
@@ -148,7 +887,7 @@ impl Project {
 
         let path = path.unwrap();
 
-        match Project::from_slal(path) {
+        match Project::from_slal(path, &Default::default()) {
             Ok(prjct) => {
                 *self = prjct;
                 Ok(())
@@ -157,7 +896,7 @@ impl Project {
         }
     }
 
-    pub fn from_slal(path: PathBuf) -> Result<Project, String> {
+    pub fn from_slal(path: PathBuf, options: &ImportOptions) -> Result<Project, String> {
         let file = fs::File::open(&path).map_err(|e| e.to_string())?;
 
         let slal: serde_json::Value =
@@ -169,6 +908,20 @@ impl Project {
             .ok_or("Missing name attribute")?
             .into();
 
+        if let Some(info_path) = options
+            .info_file
+            .as_ref()
+            .and_then(|name| path.parent().and_then(|dir| Some(dir.join(name))))
+            .filter(|info_path| info_path.is_file())
+        {
+            // the info file is optional metadata, a broken one should not prevent the import
+            match read_info_author(&info_path) {
+                Ok(Some(author)) => prjct.pack_author = author,
+                Ok(None) => {}
+                Err(e) => warn!("Unable to read author from {}: {}", info_path.display(), e),
+            }
+        }
+
         let anims = slal["animations"]
             .as_array()
             .ok_or("Missing animations attribute")?;
@@ -204,7 +957,7 @@ impl Project {
                 for (i, evt) in events.iter().enumerate() {
                     let edit_position = &mut scene.stages[i].positions[n];
                     edit_position.event =
-                        vec![evt["id"].as_str().ok_or("Missing id attribute")?.into()];
+                        EventSpec::Single(evt["id"].as_str().ok_or("Missing id attribute")?.into());
                     match sex.as_str() {
                         "male" | "type" => {
                             edit_position.sex = Sex {
@@ -231,6 +984,7 @@ impl Project {
                             edit_position.race = map_legacy_to_racekey(
                                 position["race"].as_str().unwrap_or(crt_race),
                             )?;
+                            edit_position.offset = default_offset_for(&edit_position.race);
                         }
                         "creaturefemale" => {
                             edit_position.sex = Sex {
@@ -241,6 +995,7 @@ impl Project {
                             edit_position.race = map_legacy_to_racekey(
                                 position["race"].as_str().unwrap_or(crt_race),
                             )?;
+                            edit_position.offset = default_offset_for(&edit_position.race);
                         }
                         _ => {
                             return Err(format!("Unrecognized gender: {}", sex));
@@ -261,8 +1016,11 @@ impl Project {
                 })
                 .unwrap_or_default();
             let stage_extra = animation["stage"].as_array();
+            let mut leadin = vec![];
+            let oral = tags.iter().any(|tag| tag == "oral");
             for (i, stage) in scene.stages.iter_mut().enumerate() {
                 stage.tags = tags.clone();
+                stage.extra.open_mouth = oral;
                 if let Some(extra_vec) = stage_extra {
                     for extra in extra_vec {
                         let n = extra["number"].as_i64().unwrap_or(-1);
@@ -270,24 +1028,38 @@ impl Project {
                             continue;
                         }
                         stage.extra.fixed_len = extra["timer"].as_f64().unwrap_or_default() as f32;
+                        if let Some(sound) = extra["sound"].as_str() {
+                            stage.extra.sound = sound.into();
+                        }
+                        if let Some(open_mouth) = extra["open_mouth"].as_bool() {
+                            stage.extra.open_mouth = open_mouth;
+                        }
+                        if extra["leadin"].as_bool().unwrap_or_default() {
+                            leadin.push(stage.id.clone());
+                        }
                     }
                 }
             }
-            let last = scene.stages.last_mut().unwrap();
-            for position in &mut last.positions {
-                position.extra.climax = true;
+            // lead in stages are played once before the remaining stages, move them to the front of the chain
+            if !leadin.is_empty() {
+                let (mut intro, rest): (Vec<_>, Vec<_>) = scene
+                    .stages
+                    .drain(..)
+                    .partition(|stage| leadin.contains(&stage.id));
+                intro.extend(rest);
+                scene.stages = intro;
             }
-            // build graph
-            scene.root = scene.stages[0].id.clone();
-            let mut prev_id: Option<String> = None;
-            for stage in scene.stages.iter_mut().rev() {
-                let mut value = Node::default();
-                if let Some(id) = prev_id {
-                    value.dest = vec![id];
+            if !tags
+                .iter()
+                .any(|tag| options.skip_climax_tags.contains(tag))
+            {
+                let last = scene.stages.last_mut().unwrap();
+                for position in &mut last.positions {
+                    position.extra.climax = true;
                 }
-                scene.graph.insert(stage.id.clone(), value);
-                prev_id = Some(stage.id.clone());
             }
+            // build graph
+            scene.make_linear_graph();
             // add to prjct
             prjct.scenes.insert(scene.id.clone(), scene);
         }
@@ -301,129 +1073,467 @@ impl Project {
         Ok(prjct)
     }
 
+    // Build into a fresh folder in the OS temp directory, optionally removing earlier test exports of this project first
+    // Only folders named by this function are removed, that is the prefix followed by a generated id
+    pub fn quick_test_export(&self, clean_old: bool) -> Result<PathBuf, std::io::Error> {
+        let temp_dir = std::env::temp_dir();
+        // the pack name is free text, only keep characters which are safe in a folder name
+        let pack_name: String = self
+            .pack_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let prefix = format!("slsb-test-{}-", pack_name);
+        if clean_old {
+            for entry in fs::read_dir(&temp_dir)?.flatten() {
+                let path = entry.path();
+                let is_old = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_prefix(&prefix))
+                    .is_some_and(|id| {
+                        id.len() == NANOID_LENGTH
+                            && id.chars().all(|c| NANOID_ALPHABET.contains(&c))
+                    });
+                if is_old && path.is_dir() {
+                    if let Err(e) = fs::remove_dir_all(&path) {
+                        warn!("Unable to remove old test export {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+        let root_dir = temp_dir.join(format!(
+            "{}{}",
+            prefix,
+            nanoid!(NANOID_LENGTH, &NANOID_ALPHABET)
+        ));
+        fs::create_dir_all(&root_dir)?;
+        self.build(root_dir.clone(), &Default::default())?;
+        info!("Test export written to {}", root_dir.display());
+
+        Ok(root_dir)
+    }
+
     pub fn export(&self) -> Result<(), std::io::Error> {
         let path = FileDialogBuilder::new().pick_folder();
         if path.is_none() {
             return Err(std::io::Error::from(ErrorKind::Interrupted));
         }
         let root_dir = path.unwrap();
-        self.build(root_dir)
+        self.build(root_dir, &Default::default())
     }
 
-    pub fn build(&self, root_dir: PathBuf) -> Result<(), std::io::Error> {
-        println!("Compiling project {}", self.pack_name);
-        // Write binary
-        {
-            let target_dir = root_dir.join("SKSE\\SexLab\\Registry\\");
-            let mut buf: Vec<u8> = Vec::new();
-            buf.reserve(self.get_byte_size());
-            self.write_byte(&mut buf);
-            fs::create_dir_all(&target_dir)?;
-            let mut file = fs::File::create(target_dir.join(format!(
-                "{}.slr",
-                if self.pack_name.is_empty() {
-                    &self.prefix_hash
-                } else {
-                    &self.pack_name
+    // Run every check a build would go through without writing any files
+    pub fn self_check(&self) -> HealthReport {
+        let mut report = HealthReport::default();
+        if let Err(e) = self.validate() {
+            report.errors.push(e);
+        }
+        for (_, scene) in &self.scenes {
+            if scene.has_warnings {
+                report.warnings.push(format!(
+                    "{} / {}: Scene has warnings and will be skipped",
+                    scene.id, scene.name
+                ));
+                continue;
+            }
+            for diagnostic in self.scene_diagnostics(scene) {
+                report
+                    .warnings
+                    .push(format!("{} / {}: {}", scene.id, scene.name, diagnostic));
+            }
+        }
+        if report.errors.is_empty() {
+            for racekey in self.make_fnis_events(&Default::default()).keys() {
+                if map_race_to_folder(racekey).is_err() {
+                    report
+                        .errors
+                        .push(format!("Cannot find folder for RaceKey {}", racekey));
                 }
-            )))?;
-            file.write_all(&buf)?;
+            }
+        }
+        report.passed = report.errors.is_empty();
+
+        report
+    }
+
+    pub fn registry_bytes(&self, options: &BuildOptions) -> Vec<u8> {
+        if !options.scene_name_prefix.is_empty() {
+            let mut prjct = self.clone();
+            for (_, scene) in &mut prjct.scenes {
+                scene.name = format!("{}{}", options.scene_name_prefix, scene.name);
+            }
+            let options = BuildOptions {
+                scene_name_prefix: Default::default(),
+                ..options.clone()
+            };
+            return prjct.registry_bytes(&options);
+        }
+        let mut buf: Vec<u8> = Vec::with_capacity(self.get_byte_size());
+        self.write_byte_version(&mut buf, options.registry_version);
+        buf
+    }
+
+    // Build a single scene as its own pack, with a new prefix and pack name so it can be installed next to the full pack
+    pub fn export_scene_isolated(
+        &self,
+        id: &NanoID,
+        root_dir: PathBuf,
+    ) -> Result<Project, std::io::Error> {
+        let mut scene = self.get_scene(id).cloned().ok_or(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("Invalid Scene ID: {}", id),
+        ))?;
+        let mut prjct = Project::new();
+        while prjct.prefix_hash == self.prefix_hash {
+            prjct.prefix_hash = nanoid!(PREFIX_HASH_LEN, &NANOID_ALPHABET);
+        }
+        prjct.pack_name = format!("{}_{}", self.pack_name, prjct.prefix_hash);
+        prjct.pack_author = self.pack_author.clone();
+        scene.id = nanoid!(NANOID_LENGTH, &NANOID_ALPHABET);
+        prjct.scenes.insert(scene.id.clone(), scene);
+        prjct.build(root_dir, &Default::default())?;
+
+        Ok(prjct)
+    }
+
+    // Frameworks and DLCs users need to have installed, inferred from the races used in exported scenes
+    pub fn compatibility_report(&self) -> CompatReport {
+        let mut report = CompatReport::default();
+        for (_, scene) in &self.scenes {
+            if scene.has_warnings {
+                continue;
+            }
+            for stage in &scene.stages {
+                for position in &stage.positions {
+                    report.races.insert(position.race.clone());
+                }
+            }
+        }
+        for race in &report.races {
+            if race == "Human" {
+                continue;
+            }
+            report.requirements.insert("Creature Framework".into());
+            let folder = map_race_to_folder(race).unwrap_or_default();
+            if folder.starts_with("dlc01\\") || race == "Vampire Lord" {
+                report.requirements.insert("Dawnguard".into());
+            } else if folder.starts_with("dlc02\\") {
+                report.requirements.insert("Dragonborn".into());
+            }
+        }
+
+        report
+    }
+
+    // Every tag in use, lowercased and trimmed, with the number of stages using it
+    pub fn all_tags(&self) -> BTreeMap<String, usize> {
+        let mut ret = BTreeMap::new();
+        for stage in self.scenes.values().flat_map(|scene| &scene.stages) {
+            let tags: BTreeSet<String> = stage
+                .tags
+                .iter()
+                .map(|tag| tag.trim().to_lowercase())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+            for tag in tags {
+                *ret.entry(tag).or_insert(0) += 1;
+            }
+        }
+
+        ret
+    }
+
+    // Scene id and race of every creature race which none of the installed frameworks provides, humans need no framework
+    pub fn unsupported_races(&self, supported: &HashSet<String>) -> Vec<(NanoID, String)> {
+        let mut ret = BTreeSet::new();
+        for (id, scene) in &self.scenes {
+            for position in scene.stages.iter().flat_map(|stage| &stage.positions) {
+                if position.race != "Human" && !supported.contains(&position.race) {
+                    ret.insert((id.clone(), position.race.clone()));
+                }
+            }
+        }
+
+        ret.into_iter().collect()
+    }
+
+    // Overview of all exported scenes for a mod page, ordered by name
+    pub fn export_scene_table(&self, format: TableFormat) -> String {
+        let mut scenes: Vec<&Scene> = self.scenes.values().filter(|s| !s.has_warnings).collect();
+        scenes.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut rows = vec![vec![
+            "Scene".to_string(),
+            "Actors".into(),
+            "Races".into(),
+            "Tags".into(),
+            "Stages".into(),
+        ]];
+        for scene in scenes {
+            let mut races: Vec<&str> = vec![];
+            for position in scene.stages.iter().flat_map(|stage| &stage.positions) {
+                if !races.contains(&position.race.as_str()) {
+                    races.push(&position.race);
+                }
+            }
+            let tags: BTreeSet<&str> = scene
+                .stages
+                .iter()
+                .flat_map(|stage| &stage.tags)
+                .map(|tag| tag.trim())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+            rows.push(vec![
+                scene.name.clone(),
+                scene
+                    .stages
+                    .first()
+                    .map_or(0, |stage| stage.positions.len())
+                    .to_string(),
+                races.join(", "),
+                tags.into_iter().collect::<Vec<_>>().join(", "),
+                scene.stages.len().to_string(),
+            ]);
+        }
+
+        let mut ret = String::new();
+        match format {
+            TableFormat::Markdown => {
+                for (i, row) in rows.iter().enumerate() {
+                    let cells: Vec<String> = row.iter().map(|c| c.replace('|', "\\|")).collect();
+                    ret.push_str(&format!("| {} |\n", cells.join(" | ")));
+                    if i == 0 {
+                        ret.push_str(&format!("|{}\n", " --- |".repeat(row.len())));
+                    }
+                }
+            }
+            TableFormat::BBCode => {
+                ret.push_str("[table]\n");
+                for (i, row) in rows.iter().enumerate() {
+                    let cell = if i == 0 { "th" } else { "td" };
+                    ret.push_str("[tr]");
+                    for c in row {
+                        ret.push_str(&format!("[{0}]{1}[/{0}]", cell, c));
+                    }
+                    ret.push_str("[/tr]\n");
+                }
+                ret.push_str("[/table]\n");
+            }
+        }
+
+        ret
+    }
+
+    // Every id in use, graph nodes are expected to share their id with a stage of the same scene
+    pub fn all_ids(&self) -> Vec<(IdKind, NanoID)> {
+        let mut ret = vec![];
+        for (id, scene) in &self.scenes {
+            ret.push((IdKind::Scene, id.clone()));
+            for stage in &scene.stages {
+                ret.push((IdKind::Stage, stage.id.clone()));
+            }
+            for key in scene.graph.keys() {
+                ret.push((IdKind::GraphNode, key.clone()));
+            }
+        }
+
+        ret
+    }
+
+    // Ids used by more than one scene or stage, or by graph nodes of more than one scene, with every kind they are used as
+    pub fn find_id_collisions(&self) -> BTreeMap<NanoID, Vec<IdKind>> {
+        let mut uses: BTreeMap<NanoID, Vec<IdKind>> = BTreeMap::new();
+        for (kind, id) in self.all_ids() {
+            uses.entry(id).or_default().push(kind);
+        }
+        uses.retain(|_, kinds| {
+            kinds.iter().filter(|k| **k != IdKind::GraphNode).count() > 1
+                || kinds.iter().filter(|k| **k == IdKind::GraphNode).count() > 1
+        });
+        for kinds in uses.values_mut() {
+            kinds.sort();
+        }
+
+        uses
+    }
+
+    // Scenes on which no alignment work has been done yet, ordered by name
+    pub fn scenes_without_offsets(&self) -> Vec<&Scene> {
+        let mut ret: Vec<&Scene> = self
+            .scenes
+            .values()
+            .filter(|scene| scene.is_unaligned())
+            .collect();
+        ret.sort_by(|a, b| a.name.cmp(&b.name));
+
+        ret
+    }
+
+    // Warnings of every scene which has any, keyed by scene id
+    pub fn diagnostics(&self) -> BTreeMap<NanoID, Vec<String>> {
+        let mut ret = BTreeMap::new();
+        for (id, scene) in &self.scenes {
+            let diagnostics = self.scene_diagnostics(scene);
+            if !diagnostics.is_empty() {
+                ret.insert(id.clone(), diagnostics);
+            }
+        }
+
+        ret
+    }
+
+    // Warnings of a single scene, including the event naming check if the project uses a convention
+    fn scene_diagnostics(&self, scene: &Scene) -> Vec<String> {
+        let mut ret = scene.diagnostics(&self.futa_races);
+        if let Some(naming) = &self.sex_naming {
+            ret.extend(scene.validate_sex_naming(naming));
+        }
+
+        ret
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let count = self.scenes.values().filter(|s| !s.has_warnings).count();
+        if count > MAX_SCENES {
+            return Err(format!(
+                "Project has {} scenes but a pack may contain at most {}",
+                count, MAX_SCENES
+            ));
+        }
+        for (_, scene) in &self.scenes {
+            if scene.has_warnings {
+                continue;
+            }
+            Self::validate_scene(scene)?;
+        }
+
+        Ok(())
+    }
+
+    fn validate_scene(scene: &Scene) -> Result<(), String> {
+        if scene.stages.is_empty() {
+            return Err(format!("Scene {} / {} has no stages", scene.id, scene.name));
+        }
+        // races are encoded as a key in the registry, positions without a known race cannot be written
+        for stage in &scene.stages {
+            for (i, position) in stage.positions.iter().enumerate() {
+                // the first event is the one registered for the position, without it there is nothing to write
+                if position.event.first().is_none() {
+                    return Err(format!(
+                        "Scene {} / {}: Position {} in stage {} has no event",
+                        scene.id, scene.name, i, stage.id
+                    ));
+                }
+                if get_race_key_bytes(&position.race).is_none() {
+                    return Err(format!(
+                        "Scene {} / {}: Position {} in stage {} has unknown race '{}'",
+                        scene.id, scene.name, i, stage.id, position.race
+                    ));
+                }
+            }
+        }
+        for flag in scene.get_fnis_flags() {
+            if !FNIS_OPTIONS.contains(&flag.as_str()) {
+                return Err(format!(
+                    "Scene {} / {} uses unsupported FNIS option {}",
+                    scene.id, scene.name, flag
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Registry data of a single scene, as build writes it
+    pub fn scene_bytes(&self, id: &NanoID) -> Result<Vec<u8>, String> {
+        let scene = self
+            .get_scene(id)
+            .ok_or(format!("Invalid Scene ID: {}", id))?;
+        Self::validate_scene(scene)?;
+        Ok(scene.encode_bytes())
+    }
+
+    pub fn build(&self, root_dir: PathBuf, options: &BuildOptions) -> Result<(), std::io::Error> {
+        println!("Compiling project {}", self.pack_name);
+        self.validate()
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        if !(MIN_REGISTRY_VERSION..=MAX_REGISTRY_VERSION).contains(&options.registry_version) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Unsupported registry version {}, expected {} to {}",
+                    options.registry_version, MIN_REGISTRY_VERSION, MAX_REGISTRY_VERSION
+                ),
+            ));
+        }
+        if options.strict {
+            let skipped = self
+                .scenes
+                .values()
+                .filter(|scene| scene.has_warnings)
+                .map(|scene| format!("{} / {}", scene.id, scene.name))
+                .collect::<Vec<_>>();
+            if !skipped.is_empty() {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Scenes with warnings: {}", skipped.join(", ")),
+                ));
+            }
+        }
+        let mut log = BuildLog::default();
+        let registry_root = options.registry_dir(&root_dir);
+        let meshes_root = options.meshes_dir(&root_dir);
+        // Write binary
+        {
+            let target_path = self.get_registry_path(registry_root);
+            let buf = self.registry_bytes(options);
+            fs::create_dir_all(target_path.parent().unwrap())?;
+            let mut file = fs::File::create(target_path)?;
+            file.write_all(&buf)?;
         }
         // Write FNIS files
         {
-            let mut events: HashMap<&str, Vec<String>> = HashMap::new(); // map<RaceKey, Lines[]>
-            let mut control: HashSet<&str> = HashSet::from(["__BLANK__", "__DEFAULT__"]);
             for (_, scene) in &self.scenes {
                 if scene.has_warnings {
+                    log.scenes_skipped.push(scene.id.clone());
                     continue;
                 }
-                for stage in &scene.stages {
-                    for position in &stage.positions {
-                        let event = &position.event[0];
-                        if control.contains(event.as_str()) {
-                            continue;
-                        }
-                        control.insert(event);
-                        let lines = make_fnis_lines(
-                            &position.event,
-                            &self.prefix_hash,
-                            stage.extra.fixed_len > 0.0,
-                            &position.anim_obj.split(',').fold(vec![], |mut acc, x| {
-                                if !x.is_empty() {
-                                    acc.push(x.to_string());
-                                }
-                                acc
-                            }),
-                        );
-                        let mut insert = |race| {
-                            events
-                                .entry(race)
-                                .and_modify(|list| list.append(&mut lines.clone()))
-                                .or_insert(lines.clone());
-                        };
-                        let race = position.race.as_str();
-                        match race {
-                            "Canine" => {
-                                insert(&position.race);
-                                insert("Dog");
-                                insert("Wolf");
-                            }
-                            "Dog" | "Wolf" => {
-                                insert(&position.race);
-                                insert("Canine");
-                            }
-                            //  => {
-                            //     insert("Boar");
-                            //     insert("Boar (Mounted)");
-                            // }
-                            "Boar" | "Boar (Mounted)" | "Boar (Any)" => insert("Boar (Any)"),
-                            _ => insert(&position.race),
-                        }
-                    }
+                log.scenes_exported.push(scene.id.clone());
+                for diagnostic in self.scene_diagnostics(scene) {
+                    warn!("{} / {}: {}", scene.id, scene.name, diagnostic);
+                    log.warnings
+                        .push(format!("{} / {}: {}", scene.id, scene.name, diagnostic));
                 }
             }
             info!("---------------------------------------------------------");
-            for (racekey, anim_events) in events {
-                let target_folder = map_race_to_folder(racekey)
-                    .expect(format!("Cannot find folder for RaceKey {}", racekey).as_str());
-                let path = root_dir.join(format!(
-                    "meshes\\actors\\{}\\animations\\{}",
-                    target_folder, self.pack_name
-                ));
-                let crt = &target_folder[target_folder
-                    .find('\\')
-                    .and_then(|w| Some(w + 1))
-                    .unwrap_or(0)..];
-                fs::create_dir_all(&path)?;
-
-                let create = |file_path: PathBuf| -> Result<(), std::io::Error> {
-                    let name = file_path.to_str().unwrap_or("NONE".into()).to_string();
-                    let file = fs::File::create(file_path)?;
-                    let mut file = BufWriter::new(file);
-                    info!(
-                        "Adding {} lines to race {} |||||| file: {}",
-                        anim_events.len(),
-                        racekey,
-                        name
-                    );
-                    for anim_event in anim_events {
-                        writeln!(file, "{}", anim_event)?;
-                    }
-                    Ok(())
-                };
-                match crt {
-                    "character" => create(path.join(format!("FNIS_{}_List.txt", self.pack_name))),
-                    "canine" => match racekey {
-                        "Canine" => {
-                            create(path.join(format!("FNIS_{}_canine_List.txt", self.pack_name)))
-                        }
-                        "Dog" => create(path.join(format!("FNIS_{}_dog_List.txt", self.pack_name))),
-                        _ => create(path.join(format!("FNIS_{}_wolf_List.txt", self.pack_name))),
-                    },
-                    _ => create(path.join(format!("FNIS_{}_{}_List.txt", self.pack_name, crt))),
-                }?;
+            for (racekey, file_path, lines) in self.make_fnis_lists(meshes_root, options) {
+                fs::create_dir_all(file_path.parent().unwrap())?;
+
+                let name = file_path.to_str().unwrap_or("NONE").to_string();
+                let file = fs::File::create(file_path)?;
+                let mut file = BufWriter::new(file);
+                info!(
+                    "Adding {} lines to race {} |||||| file: {}",
+                    lines.len(),
+                    racekey,
+                    name
+                );
+                for anim_event in &lines {
+                    writeln!(file, "{}", anim_event)?;
+                }
+                *log.races.entry(racekey.into()).or_insert(0) += lines.len();
             }
         }
+        if options.write_log {
+            let file = fs::File::create(root_dir.join("build-log.json"))?;
+            serde_json::to_writer_pretty(BufWriter::new(file), &log)?;
+        }
         info!(
             "Successfully compiled {}",
             root_dir.to_str().unwrap_or_default()
@@ -431,6 +1541,208 @@ impl Project {
         Ok(())
     }
 
+    pub fn clean_export(
+        &self,
+        root_dir: &Path,
+        options: &BuildOptions,
+    ) -> Result<Vec<PathBuf>, std::io::Error> {
+        let meshes_root = options.meshes_dir(root_dir);
+        // every race is checked, a previous export may have included races which are no longer in use
+        // numbered chunks are only removed if the current build writes them, they may as well belong to another pack
+        let mut candidates = vec![self.get_registry_path(options.registry_dir(root_dir))];
+        for racekey in get_race_keys_string() {
+            candidates.extend(self.get_fnis_list_path(meshes_root, &racekey));
+        }
+        candidates.extend(self.fnis_output_paths(root_dir, options));
+        let mut ret = vec![];
+        for path in candidates {
+            if !path.is_file() {
+                continue;
+            }
+            fs::remove_file(&path)?;
+            info!("Removed {}", path.display());
+            ret.push(path);
+        }
+
+        Ok(ret)
+    }
+
+    pub fn fnis_output_paths(&self, root_dir: &Path, options: &BuildOptions) -> Vec<PathBuf> {
+        self.make_fnis_lists(options.meshes_dir(root_dir), options)
+            .into_iter()
+            .map(|(_, path, _)| path)
+            .collect()
+    }
+
+    // Animation files referenced by the FNIS lists of a build, keyed by the folder of their list
+    // The chunks of a split list each have their own folder and only expect the animations of their own lines
+    pub fn hkx_manifest(
+        &self,
+        root_dir: &Path,
+        options: &BuildOptions,
+    ) -> BTreeMap<PathBuf, BTreeSet<String>> {
+        let mut ret: BTreeMap<PathBuf, BTreeSet<String>> = BTreeMap::new();
+        for (_, path, lines) in self.make_fnis_lists(options.meshes_dir(root_dir), options) {
+            let files = lines
+                .iter()
+                .filter_map(|line| line.split_whitespace().find(|word| word.ends_with(".hkx")));
+            if let Some(dir) = path.parent() {
+                ret.entry(dir.to_path_buf())
+                    .or_default()
+                    .extend(files.map(|file| file.to_string()));
+            }
+        }
+        ret.retain(|_, files| !files.is_empty());
+
+        ret
+    }
+
+    // Animation files of a build into root_dir which are missing from the folder of the FNIS list referencing them
+    pub fn check_hkx_present(&self, root_dir: &Path, options: &BuildOptions) -> Vec<PathBuf> {
+        self.hkx_manifest(root_dir, options)
+            .into_iter()
+            .flat_map(|(dir, files)| files.into_iter().map(move |file| dir.join(file)))
+            .filter(|path| !path.is_file())
+            .collect()
+    }
+
+    pub fn fnis_folders(&self, root_dir: &Path, options: &BuildOptions) -> BTreeSet<PathBuf> {
+        self.fnis_output_paths(root_dir, options)
+            .iter()
+            .filter_map(|path| path.parent().and_then(|dir| Some(dir.to_path_buf())))
+            .collect()
+    }
+
+    // Every FNIS list written by build as (RaceKey, path, lines), lists above the split threshold are written in chunks
+    // Validation ensures every race of an exported scene has a folder, no list is dropped during a build
+    fn make_fnis_lists(
+        &self,
+        meshes_root: &Path,
+        options: &BuildOptions,
+    ) -> Vec<(&str, PathBuf, Vec<String>)> {
+        let mut ret = vec![];
+        for (racekey, anim_events) in self.make_fnis_events(options) {
+            for (n, chunk) in split_fnis_lines(&anim_events, options.split_threshold)
+                .into_iter()
+                .enumerate()
+            {
+                let pack_name = fnis_chunk_name(&self.pack_name, n);
+                if let Some(path) = make_fnis_list_path(meshes_root, racekey, &pack_name) {
+                    ret.push((racekey, path, chunk.to_vec()));
+                }
+            }
+        }
+        if options.empty_race_files {
+            // some frameworks require the list to exist even if no animation is registered for it
+            for (_, scene) in &self.scenes {
+                if scene.has_warnings {
+                    continue;
+                }
+                for position in scene.stages.iter().flat_map(|stage| &stage.positions) {
+                    let race = position.race.as_str();
+                    if let Some(path) = self.get_fnis_list_path(meshes_root, race) {
+                        if !ret.iter().any(|(_, other, _)| other == &path) {
+                            ret.push((race, path, vec![]));
+                        }
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+
+    // map<RaceKey, Lines[]>
+    fn make_fnis_events(&self, options: &BuildOptions) -> HashMap<&str, Vec<String>> {
+        let mut events: HashMap<&str, Vec<String>> = HashMap::new();
+        // the same event may be used by different races, every race has its own behavior so only dedup within a race
+        let mut control: HashSet<(&str, String)> = HashSet::new();
+        for (_, scene) in &self.scenes {
+            if scene.has_warnings {
+                continue;
+            }
+            let flags = scene.get_fnis_flags();
+            for stage in &scene.stages {
+                for position in &stage.positions {
+                    let position_event = match &options.default_event {
+                        Some(substitute) => EventSpec::from(
+                            position
+                                .event
+                                .as_slice()
+                                .iter()
+                                .map(|e| match e.as_str() {
+                                    "__DEFAULT__" => substitute.clone(),
+                                    _ => e.clone(),
+                                })
+                                .collect::<Vec<_>>(),
+                        ),
+                        None => position.event.clone(),
+                    };
+                    let event = position_event.first().expect("Position missing event");
+                    if CONTROL_EVENTS.contains(&event.as_str()) {
+                        continue;
+                    }
+                    let lines = make_fnis_lines(
+                        &position_event,
+                        &self.prefix_hash,
+                        stage.extra.fixed_len > 0.0,
+                        &flags,
+                        &position.anim_obj.split(',').fold(vec![], |mut acc, x| {
+                            if !x.is_empty() {
+                                acc.push(x.to_string());
+                            }
+                            acc
+                        }),
+                    );
+                    let mut insert = |race| {
+                        if !control.insert((race, event.clone())) {
+                            return;
+                        }
+                        events
+                            .entry(race)
+                            .and_modify(|list| list.append(&mut lines.clone()))
+                            .or_insert(lines.clone());
+                    };
+                    let race = position.race.as_str();
+                    match race {
+                        "Canine" => {
+                            insert(&position.race);
+                            insert("Dog");
+                            insert("Wolf");
+                        }
+                        "Dog" | "Wolf" => {
+                            insert(&position.race);
+                            insert("Canine");
+                        }
+                        //  => {
+                        //     insert("Boar");
+                        //     insert("Boar (Mounted)");
+                        // }
+                        "Boar" | "Boar (Mounted)" | "Boar (Any)" => insert("Boar (Any)"),
+                        _ => insert(&position.race),
+                    }
+                }
+            }
+        }
+
+        events
+    }
+
+    fn get_registry_path(&self, root_dir: &Path) -> PathBuf {
+        root_dir.join("SKSE\\SexLab\\Registry\\").join(format!(
+            "{}.slr",
+            if self.pack_name.is_empty() {
+                &self.prefix_hash
+            } else {
+                &self.pack_name
+            }
+        ))
+    }
+
+    fn get_fnis_list_path(&self, root_dir: &Path, racekey: &str) -> Option<PathBuf> {
+        make_fnis_list_path(root_dir, racekey, &self.pack_name)
+    }
+
     pub fn import_offset(&mut self) -> Result<(), String> {
         let path = FileDialogBuilder::new()
             .add_filter("Offset File", vec!["yaml"].as_slice())
@@ -440,6 +1752,77 @@ impl Project {
         let offsetfile: serde_yaml::Mapping =
             serde_yaml::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
 
+        self.apply_offset(offsetfile, Default::default())?;
+        Ok(())
+    }
+
+    pub fn import_offset_dir(
+        &mut self,
+        dir: PathBuf,
+        options: &ImportOptions,
+    ) -> Result<(usize, usize), String> {
+        let mut matched = 0;
+        let mut missed = 0;
+        for entry in fs::read_dir(&dir).map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if !path.is_file() || path.extension() != Some(OsStr::new("yaml")) {
+                continue;
+            }
+            let file = fs::File::open(&path).map_err(|e| e.to_string())?;
+            let offsetfile: serde_yaml::Mapping = serde_yaml::from_reader(BufReader::new(file))
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            let (m, n) = self.apply_offset(offsetfile, options.offset_convention)?;
+            matched += m;
+            missed += n;
+        }
+        info!(
+            "Imported offsets from {}, matched {} scenes, missed {} scenes",
+            dir.display(),
+            matched,
+            missed
+        );
+
+        Ok((matched, missed))
+    }
+
+    // returns the number of scenes in the offset file which could and could not be found in this project
+    fn apply_offset(
+        &mut self,
+        mut offsetfile: serde_yaml::Mapping,
+        convention: OffsetConvention,
+    ) -> Result<(usize, usize), String> {
+        // files written before the version marker was introduced use the v1 layout
+        let version = match offsetfile.remove(&OFFSET_VERSION_KEY.into()) {
+            Some(v) => v
+                .as_u64()
+                .ok_or("Not a valid offset file, expected number for version".to_string())?,
+            None => 1,
+        };
+        match version {
+            1 => {
+                let mut offsetfile = ungroup_offset(offsetfile)?;
+                if !convention.is_identity() {
+                    for (_, stages) in offsetfile.iter_mut() {
+                        for (_, positions) in stages.as_mapping_mut().into_iter().flatten() {
+                            if let Some(positions) = positions.as_sequence_mut() {
+                                convert_offsets(positions, |offset| convention.import(offset));
+                            }
+                        }
+                    }
+                }
+                self.apply_offset_v1(offsetfile)
+            }
+            _ => Err(format!("Unsupported offset file version {}", version)),
+        }
+    }
+
+    // v1: { scene id: { stage id: [ { transform: { Location: [x, y, z], Rotation: r } } ] } }
+    fn apply_offset_v1(
+        &mut self,
+        offsetfile: serde_yaml::Mapping,
+    ) -> Result<(usize, usize), String> {
+        let mut matched = 0;
+        let mut missed = 0;
         for (scene_id_v, stages_v) in offsetfile {
             if !stages_v.is_mapping() {
                 continue;
@@ -454,12 +1837,125 @@ impl Project {
                         .as_mapping()
                         .ok_or(format!("Expected mapping in scene {}", scene_id))?,
                 )?;
+                matched += 1;
+            } else {
+                missed += 1;
             }
         }
 
+        Ok((matched, missed))
+    }
+
+    // Ask for a file to write the given offsets to, as created by export_offset or one of its variants
+    pub fn save_offset(&self, offsets: &serde_yaml::Mapping) -> Result<(), String> {
+        let path = FileDialogBuilder::new()
+            .set_file_name("Offset.yaml")
+            .add_filter("Offset File", vec!["yaml"].as_slice())
+            .save_file()
+            .ok_or("No path to export offsets to".to_string())?;
+        self.write_offset(path, offsets)
+    }
+
+    pub fn write_offset(&self, path: PathBuf, offsets: &serde_yaml::Mapping) -> Result<(), String> {
+        let file = fs::File::create(&path).map_err(|e| e.to_string())?;
+        serde_yaml::to_writer(BufWriter::new(file), offsets).map_err(|e| e.to_string())?;
+        println!("Exported offsets of {}", self.pack_name);
         Ok(())
     }
 
+    // Offsets of all stages, keyed by scene and stage id
+    pub fn export_offset(&self, options: &BuildOptions) -> serde_yaml::Mapping {
+        let mut ret = serde_yaml::Mapping::new();
+        ret.insert(OFFSET_VERSION_KEY.into(), OFFSET_VERSION.into());
+        for (scene_id, scene) in &self.scenes {
+            let mut stages = serde_yaml::Mapping::new();
+            for stage in &scene.stages {
+                stages.insert(
+                    stage.id.as_str().into(),
+                    export_stage_offset(stage, options.offset_convention),
+                );
+            }
+            ret.insert(scene_id.as_str().into(), stages.into());
+        }
+
+        ret
+    }
+
+    // Same offsets as export_offset, but with the scenes grouped under the first tag of each stage for easier reading
+    // Every stage is listed exactly once, importing the file has the same effect as importing the flat form
+    pub fn export_offset_grouped(&self, options: &BuildOptions) -> serde_yaml::Mapping {
+        let mut groups: BTreeMap<&str, BTreeMap<&str, serde_yaml::Mapping>> = BTreeMap::new();
+        for (scene_id, scene) in &self.scenes {
+            for stage in &scene.stages {
+                let tag = stage
+                    .tags
+                    .first()
+                    .map(|tag| tag.as_str())
+                    .unwrap_or(OFFSET_UNTAGGED);
+                groups
+                    .entry(tag)
+                    .or_default()
+                    .entry(scene_id.as_str())
+                    .or_default()
+                    .insert(
+                        stage.id.as_str().into(),
+                        export_stage_offset(stage, options.offset_convention),
+                    );
+            }
+        }
+        let mut grouped = serde_yaml::Mapping::new();
+        for (tag, scenes) in groups {
+            let mut group = serde_yaml::Mapping::new();
+            for (scene_id, stages) in scenes {
+                group.insert(scene_id.into(), stages.into());
+            }
+            grouped.insert(tag.into(), group.into());
+        }
+        let mut ret = serde_yaml::Mapping::new();
+        ret.insert(OFFSET_VERSION_KEY.into(), OFFSET_VERSION.into());
+        ret.insert(OFFSET_GROUPED_KEY.into(), grouped.into());
+
+        ret
+    }
+
+    // Offsets of all stages which differ from the equivalent stage in the given baseline project
+    pub fn export_offset_delta(
+        &self,
+        baseline: &Project,
+        options: &BuildOptions,
+    ) -> serde_yaml::Mapping {
+        let mut ret = serde_yaml::Mapping::new();
+        ret.insert(OFFSET_VERSION_KEY.into(), OFFSET_VERSION.into());
+        for (scene_id, scene) in &self.scenes {
+            let base_scene = baseline.get_scene(scene_id);
+            let mut stages = serde_yaml::Mapping::new();
+            for stage in &scene.stages {
+                let changed = match base_scene.and_then(|s| s.get_stage(&stage.id)) {
+                    Some(base_stage) => {
+                        base_stage.positions.len() != stage.positions.len()
+                            || stage
+                                .positions
+                                .iter()
+                                .zip(&base_stage.positions)
+                                .any(|(a, b)| a.offset.differs(&b.offset))
+                    }
+                    None => true,
+                };
+                if changed {
+                    stages.insert(
+                        stage.id.as_str().into(),
+                        export_stage_offset(stage, options.offset_convention),
+                    );
+                }
+            }
+            if !stages.is_empty() {
+                ret.insert(scene_id.as_str().into(), stages.into());
+            }
+        }
+
+        ret
+    }
+
     fn set_project_name_from_path(&mut self, path: &PathBuf) -> () {
         self.pack_name = String::from(
             path.file_name() // ...\\{project.slsb.json}
@@ -491,8 +1987,11 @@ impl EncodeBinary for Project {
     }
 
     fn write_byte(&self, buf: &mut Vec<u8>) -> () {
+        self.write_byte_version(buf, REGISTRY_VERSION)
+    }
+
+    fn write_byte_version(&self, buf: &mut Vec<u8>, version: u8) -> () {
         // version
-        let version: u8 = 3;
         buf.push(version);
         // project
         buf.extend_from_slice(&(self.pack_name.len() as u64).to_be_bytes());
@@ -500,7 +1999,9 @@ impl EncodeBinary for Project {
         buf.extend_from_slice(&(self.pack_author.len() as u64).to_be_bytes());
         buf.extend_from_slice(self.pack_author.as_bytes());
         buf.extend_from_slice(self.prefix_hash.as_bytes());
-        buf.extend_from_slice(&(self.scenes.len() as u64).to_be_bytes());
+        // only scenes without warnings are written, same as counted by validate
+        let count = self.scenes.values().filter(|s| !s.has_warnings).count();
+        buf.extend_from_slice(&(count as u64).to_be_bytes());
         for (_, scene) in &self.scenes {
             if scene.has_warnings {
                 continue;
@@ -508,7 +2009,1365 @@ impl EncodeBinary for Project {
             if scene.stages.len() == 0 {
                 panic!("Empty Scene whilst building files");
             }
-            scene.write_byte(buf);
+            scene.write_byte_version(buf, version);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::define::scene::SceneBuilder;
+
+    fn make_scene(name: &str, race: &str) -> Scene {
+        SceneBuilder::new(name)
+            .actor(Sex::default(), race)
+            .stage()
+            .position_event(0, &format!("{}_A1_S1", name))
+            .build()
+            .unwrap()
+    }
+
+    fn make_project(scenes: Vec<Scene>) -> Project {
+        let mut ret = Project::new();
+        ret.pack_name = "Test".into();
+        for scene in scenes {
+            ret.scenes.insert(scene.id.clone(), scene);
+        }
+        ret
+    }
+
+    fn make_temp_dir() -> PathBuf {
+        let ret = std::env::temp_dir().join(format!(
+            "slsb-unit-{}",
+            nanoid!(NANOID_LENGTH, &NANOID_ALPHABET)
+        ));
+        fs::create_dir_all(&ret).unwrap();
+        ret
+    }
+
+    fn offset_yaml(scene: &Scene, x: f32) -> String {
+        format!(
+            "{}:\n  {}:\n    - transform:\n        Location: [{}, 0.0, 0.0]\n        Rotation: 0.0\n",
+            scene.id, scene.stages[0].id, x
+        )
+    }
+
+    #[test]
+    fn import_offset_dir_applies_every_yaml() {
+        let a = make_scene("A", "Human");
+        let b = make_scene("B", "Human");
+        let dir = make_temp_dir();
+        fs::write(dir.join("a.yaml"), offset_yaml(&a, 10.0)).unwrap();
+        fs::write(dir.join("b.yaml"), offset_yaml(&b, 20.0)).unwrap();
+        fs::write(dir.join("notes.txt"), "not an offset file").unwrap();
+        let mut prjct = make_project(vec![a.clone(), b.clone()]);
+
+        let result = prjct.import_offset_dir(dir.clone(), &Default::default());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result, Ok((2, 0)));
+        let offset = |id: &NanoID| prjct.get_scene(id).unwrap().stages[0].positions[0].offset.x;
+        assert_eq!(offset(&a.id), 10.0);
+        assert_eq!(offset(&b.id), 20.0);
+    }
+
+    #[test]
+    fn fnis_output_paths_follow_race_folders() {
+        let prjct = make_project(vec![make_scene("A", "Human"), make_scene("B", "Wolf")]);
+        let root = Path::new("Data");
+        let mut paths = prjct.fnis_output_paths(root, &Default::default());
+        paths.sort();
+        let mut expected = vec![
+            root.join("meshes\\actors\\character\\animations\\Test")
+                .join("FNIS_Test_List.txt"),
+            root.join("meshes\\actors\\canine\\animations\\Test")
+                .join("FNIS_Test_canine_List.txt"),
+            root.join("meshes\\actors\\canine\\animations\\Test")
+                .join("FNIS_Test_wolf_List.txt"),
+        ];
+        expected.sort();
+        assert_eq!(paths, expected);
+    }
+
+    #[test]
+    fn clean_export_only_removes_own_files() {
+        let prjct = make_project(vec![make_scene("A", "Human")]);
+        let dir = make_temp_dir();
+        let own = vec![
+            prjct.get_registry_path(&dir),
+            prjct.get_fnis_list_path(&dir, "Human").unwrap(),
+            prjct.get_fnis_list_path(&dir, "Horse").unwrap(),
+        ];
+        let mut other = make_project(vec![]);
+        other.pack_name = "Other".into();
+        let foreign = vec![
+            other.get_registry_path(&dir),
+            make_fnis_list_path(&dir, "Human", "Other").unwrap(),
+            own[1].with_file_name("Test_A1_S1.hkx"),
+        ];
+        for path in own.iter().chain(&foreign) {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let mut removed = prjct.clean_export(&dir, &Default::default()).unwrap();
+        removed.sort();
+        let remaining: Vec<bool> = foreign.iter().map(|path| path.is_file()).collect();
+        fs::remove_dir_all(&dir).unwrap();
+        let mut expected = own.clone();
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert_eq!(remaining, vec![true, true, true]);
+    }
+
+    #[test]
+    fn build_log_counts_scenes() {
+        let mut skipped = make_scene("B", "Human");
+        skipped.has_warnings = true;
+        let prjct = make_project(vec![make_scene("A", "Human"), skipped]);
+        let dir = make_temp_dir();
+        let options = BuildOptions {
+            write_log: true,
+            ..Default::default()
+        };
+
+        let result = prjct.build(dir.clone(), &options);
+        let log = fs::read_to_string(dir.join("build-log.json"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_ok());
+        let log: serde_json::Value = serde_json::from_str(&log.unwrap()).unwrap();
+        assert_eq!(log["scenes_exported"].as_array().unwrap().len(), 1);
+        assert_eq!(log["scenes_skipped"].as_array().unwrap().len(), 1);
+        assert_eq!(log["races"]["Human"], 1);
+    }
+
+    fn write_slal(dir: &Path, animations: serde_json::Value) -> PathBuf {
+        let path = dir.join("Test.json");
+        let slal = serde_json::json!({ "name": "Test", "animations": animations });
+        fs::write(&path, slal.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn slal_cuddle_scene_has_no_climax() {
+        let dir = make_temp_dir();
+        let path = write_slal(
+            &dir,
+            serde_json::json!([
+                {
+                    "name": "Cuddle",
+                    "tags": "Cuddle, Loving",
+                    "actors": [
+                        { "type": "Female", "stages": [{ "id": "Cuddle_A1_S1" }, { "id": "Cuddle_A1_S2" }] },
+                        { "type": "Male", "stages": [{ "id": "Cuddle_A2_S1" }, { "id": "Cuddle_A2_S2" }] }
+                    ]
+                },
+                {
+                    "name": "Kiss",
+                    "tags": "Kissing",
+                    "actors": [{ "type": "Female", "stages": [{ "id": "Kiss_A1_S1" }] }]
+                }
+            ]),
+        );
+
+        let prjct = Project::from_slal(path, &Default::default());
+        fs::remove_dir_all(&dir).unwrap();
+        let prjct = prjct.unwrap();
+        let climax = |name: &str| {
+            let scene = prjct.scenes.values().find(|s| s.name == name).unwrap();
+            scene
+                .stages
+                .iter()
+                .flat_map(|stage| &stage.positions)
+                .any(|position| position.extra.climax)
+        };
+        assert!(!climax("Cuddle"));
+        assert!(climax("Kiss"));
+    }
+
+    #[test]
+    fn portable_export_round_trips() {
+        let mut prjct = make_project(vec![make_scene("A", "Human"), make_scene("B", "Wolf")]);
+        prjct.pack_author = "Author".into();
+        for scene in prjct.scenes.values_mut() {
+            scene.stages[0].positions[0].offset.x = 12.5;
+        }
+        let dir = make_temp_dir();
+        let path = dir.join("Test.json");
+        prjct.write_portable(path.clone()).unwrap();
+
+        let imported = Project::from_portable(fs::File::open(&path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        let imported = imported.unwrap();
+        assert_eq!(
+            serde_json::to_value(&imported).unwrap(),
+            serde_json::to_value(&prjct).unwrap()
+        );
+        assert!(imported.changed_scenes_since_save().is_empty());
+    }
+
+    #[test]
+    fn portable_import_repairs_like_a_project_file() {
+        let mut prjct = make_project(vec![make_scene("A", "Frostbite Spider")]);
+        for scene in prjct.scenes.values_mut() {
+            scene.graph.clear();
+        }
+        let dir = make_temp_dir();
+        let path = dir.join("Test.json");
+        prjct.write_portable(path.clone()).unwrap();
+
+        let imported = Project::from_portable(fs::File::open(&path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        let scene = imported.unwrap().scenes.into_values().next().unwrap();
+        assert_eq!(scene.stages[0].positions[0].race, "Spider");
+        assert!(scene.validate_graph().is_ok());
+    }
+
+    #[test]
+    fn offset_delta_only_lists_changed_stages() {
+        let a = make_scene("A", "Human");
+        let b = make_scene("B", "Human");
+        let baseline = make_project(vec![a.clone(), b.clone()]);
+        let mut prjct = baseline.clone();
+        prjct.get_scene_mut(&a.id).unwrap().stages[0].positions[0]
+            .offset
+            .y = 5.0;
+        prjct.get_scene_mut(&b.id).unwrap().stages[0].positions[0]
+            .offset
+            .y = 0.0001;
+
+        let delta = prjct.export_offset_delta(&baseline, &Default::default());
+        assert_eq!(delta.len(), 2);
+        assert!(delta.contains_key(&OFFSET_VERSION_KEY.into()));
+        let stages = delta[&a.id.as_str().into()].as_mapping().unwrap();
+        assert_eq!(stages.len(), 1);
+        assert!(stages.contains_key(&a.stages[0].id.as_str().into()));
+    }
+
+    #[test]
+    fn scene_limit_is_enforced() {
+        let template = make_scene("A", "Human");
+        let mut prjct = make_project(vec![]);
+        for _ in 0..=MAX_SCENES {
+            let mut scene = template.clone();
+            scene.id = nanoid!(NANOID_LENGTH, &NANOID_ALPHABET);
+            prjct.scenes.insert(scene.id.clone(), scene);
+        }
+        assert_eq!(prjct.scenes.len(), MAX_SCENES + 1);
+        assert!(prjct.validate().is_err());
+        let result = prjct.build(PathBuf::from("unused"), &Default::default());
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // scenes with warnings are not exported and do not count towards the limit
+        prjct.scenes.values_mut().next().unwrap().has_warnings = true;
+        assert!(prjct.validate().is_ok());
+    }
+
+    #[test]
+    fn registry_counts_exported_scenes() {
+        let mut skipped = make_scene("B", "Human");
+        skipped.has_warnings = true;
+        let prjct = make_project(vec![make_scene("A", "Human"), skipped]);
+
+        let buf = prjct.registry_bytes(&Default::default());
+        let mut reader = ByteReader::new(&buf);
+        reader.read_u8().unwrap();
+        reader.read_string().unwrap();
+        reader.read_string().unwrap();
+        reader.read_str(PREFIX_HASH_LEN).unwrap();
+        assert_eq!(reader.read_u64().unwrap(), 1);
+    }
+
+    #[test]
+    fn self_check_passes_healthy_project() {
+        let prjct = make_project(vec![make_scene("A", "Human"), make_scene("B", "Wolf")]);
+        let report = prjct.self_check();
+        assert!(report.passed);
+        assert!(report.errors.is_empty());
+    }
+
+    #[test]
+    fn self_check_fails_broken_project() {
+        let mut empty = make_scene("A", "Human");
+        empty.stages.clear();
+        let report = make_project(vec![empty]).self_check();
+        assert!(!report.passed);
+        assert_eq!(report.errors.len(), 1);
+
+        let mut no_event = make_scene("B", "Human");
+        no_event.stages[0].positions[0].event = EventSpec::Chain(vec![]);
+        let prjct = make_project(vec![no_event]);
+        let report = prjct.self_check();
+        assert!(!report.passed);
+        assert!(report.errors.iter().any(|e| e.contains("has no event")));
+        let result = prjct.build(PathBuf::from("unused"), &Default::default());
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn scene_fnis_flags_apply_to_all_lines() {
+        let mut scene = SceneBuilder::new("Duo")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Duo_A1_S1")
+            .position_event(0, "Duo_A1_S2")
+            .position_event(1, "Duo_A2_S1")
+            .stage()
+            .position_event(0, "Duo_A1_S3")
+            .position_event(1, "Duo_A2_S3")
+            .build()
+            .unwrap();
+        scene.fnis_flags = "md".into();
+        let prjct = make_project(vec![scene]);
+
+        let events = prjct.make_fnis_events(&Default::default());
+        let lines = &events["Human"];
+        assert_eq!(lines.len(), 5);
+        for line in lines {
+            assert!(line.contains(" -md "), "{}", line);
+        }
+    }
+
+    #[test]
+    fn slal_leadin_stage_becomes_root() {
+        let dir = make_temp_dir();
+        let path = write_slal(
+            &dir,
+            serde_json::json!([{
+                "name": "Lead",
+                "tags": "",
+                "actors": [{
+                    "type": "Female",
+                    "stages": [{ "id": "Lead_A1_S1" }, { "id": "Lead_A1_S2" }, { "id": "Lead_A1_S3" }]
+                }],
+                "stage": [{ "number": 2, "leadin": true }]
+            }]),
+        );
+
+        let prjct = Project::from_slal(path, &Default::default());
+        fs::remove_dir_all(&dir).unwrap();
+        let scene = prjct.unwrap().scenes.into_values().next().unwrap();
+        let root = scene.get_stage(&scene.root).unwrap();
+        assert_eq!(
+            root.positions[0].event,
+            EventSpec::Single("Lead_A1_S3".into())
+        );
+        let next = &scene.graph[&scene.root].dest;
+        assert_eq!(next.len(), 1);
+        assert_eq!(
+            scene.get_stage(&next[0]).unwrap().positions[0].event,
+            EventSpec::Single("Lead_A1_S1".into())
+        );
+        assert!(scene.validate_graph().is_ok());
+    }
+
+    #[test]
+    fn registry_version_2_omits_schlong() {
+        let mut scene = SceneBuilder::new("Duo")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Duo_A1_S1")
+            .position_event(1, "Duo_A2_S1")
+            .stage()
+            .position_event(0, "Duo_A1_S2")
+            .position_event(1, "Duo_A2_S2")
+            .build()
+            .unwrap();
+        for stage in &mut scene.stages {
+            stage.positions[0].schlong = 5;
+        }
+        let prjct = make_project(vec![scene]);
+        let bytes = |version| {
+            prjct.registry_bytes(&BuildOptions {
+                registry_version: version,
+                ..Default::default()
+            })
+        };
+
+        let v2 = bytes(2);
+        let v3 = bytes(3);
+        assert_eq!(v2[0], 2);
+        // one schlong byte for each of the 2 positions in both stages
+        assert_eq!(v3.len() - v2.len(), 4);
+        let schlong = |buf: &[u8]| {
+            let decoded = Project::from_registry_bytes(buf).unwrap();
+            let scene = decoded.scenes.into_values().next().unwrap();
+            scene.stages[0].positions[0].schlong
+        };
+        assert_eq!(schlong(&v2), 0);
+        assert_eq!(schlong(&v3), 5);
+    }
+
+    #[test]
+    fn build_accepts_registry_version_2() {
+        let prjct = make_project(vec![make_scene("A", "Human")]);
+        let dir = make_temp_dir();
+        let options = BuildOptions {
+            registry_version: 2,
+            ..Default::default()
+        };
+        let result = prjct.build(dir.clone(), &options);
+        let registry = fs::read(prjct.get_registry_path(&dir));
+        let too_old = prjct.build(
+            dir.clone(),
+            &BuildOptions {
+                registry_version: 1,
+                ..Default::default()
+            },
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(registry.unwrap()[0], 2);
+        assert!(too_old.is_err());
+    }
+
+    #[test]
+    fn fnis_folders_of_mixed_project() {
+        let prjct = make_project(vec![
+            make_scene("A", "Human"),
+            make_scene("B", "Horse"),
+            make_scene("C", "Wolf"),
+        ]);
+        let root = Path::new("Data");
+        let folders = prjct.fnis_folders(root, &Default::default());
+        let expected: BTreeSet<PathBuf> = ["character", "horse", "canine"]
+            .iter()
+            .map(|folder| root.join(format!("meshes\\actors\\{}\\animations\\Test", folder)))
+            .collect();
+        assert_eq!(folders, expected);
+    }
+
+    #[test]
+    fn loading_migrates_deprecated_races() {
+        let mut prjct = make_project(vec![make_scene("A", "Frostbite Spider")]);
+        let dir = make_temp_dir();
+        let path = dir.join("Test.slsb.json");
+        prjct.write(path.clone()).unwrap();
+
+        let loaded = Project::from_file(fs::File::open(&path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        let scene = loaded.unwrap().scenes.into_values().next().unwrap();
+        assert_eq!(scene.stages[0].positions[0].race, "Spider");
+    }
+
+    #[test]
+    fn scene_bytes_are_embedded_in_registry() {
+        let scene = make_scene("A", "Human");
+        let prjct = make_project(vec![scene.clone()]);
+
+        let buf = prjct.registry_bytes(&Default::default());
+        let scene_bytes = scene.encode_bytes();
+        // version, pack name, author, prefix and scene count precede the scenes
+        let start =
+            1 + 8 + prjct.pack_name.len() + 8 + prjct.pack_author.len() + PREFIX_HASH_LEN + 8;
+        assert_eq!(&buf[start..], scene_bytes.as_slice());
+        assert_eq!(prjct.scene_bytes(&scene.id).unwrap(), scene_bytes);
+    }
+
+    #[test]
+    fn scene_bytes_of_invalid_scenes_are_rejected() {
+        let mut scene = make_scene("A", "Human");
+        scene.stages[0].positions[0].event = EventSpec::Chain(vec![]);
+        let prjct = make_project(vec![scene.clone()]);
+        assert!(prjct
+            .scene_bytes(&scene.id)
+            .unwrap_err()
+            .contains("has no event"));
+        assert!(prjct.scene_bytes(&"missing".into()).is_err());
+    }
+
+    #[test]
+    fn new_positions_use_default_race() {
+        let mut prjct = make_project(vec![]);
+        prjct.set_default_race("Wolf".into()).unwrap();
+        assert_eq!(prjct.make_position().race, "Wolf");
+        assert!(prjct
+            .make_stage(2)
+            .positions
+            .iter()
+            .all(|position| position.race == "Wolf"));
+
+        assert!(prjct.set_default_race("Not A Race".into()).is_err());
+        assert_eq!(prjct.default_race, "Wolf");
+    }
+
+    #[test]
+    fn strict_build_fails_on_warning_scenes() {
+        let mut skipped = make_scene("B", "Human");
+        skipped.has_warnings = true;
+        let mut prjct = make_project(vec![make_scene("A", "Human"), skipped.clone()]);
+        let dir = make_temp_dir();
+        let options = BuildOptions {
+            strict: true,
+            ..Default::default()
+        };
+
+        let failed = prjct.build(dir.clone(), &options);
+        let lenient = prjct.build(dir.clone(), &Default::default());
+        prjct.scenes.remove(&skipped.id);
+        let passed = prjct.build(dir.clone(), &options);
+        fs::remove_dir_all(&dir).unwrap();
+        let failed = failed.unwrap_err();
+        assert_eq!(failed.kind(), ErrorKind::InvalidData);
+        assert!(failed.to_string().contains(&skipped.id));
+        assert!(lenient.is_ok());
+        assert!(passed.is_ok());
+    }
+
+    #[test]
+    fn shared_event_is_listed_for_every_race() {
+        let make = |race| {
+            SceneBuilder::new(race)
+                .actor(Sex::default(), race)
+                .stage()
+                .position_event(0, "Shared_A1_S1")
+                .build()
+                .unwrap()
+        };
+        let prjct = make_project(vec![make("Human"), make("Horse"), make("Human")]);
+
+        let events = prjct.make_fnis_events(&Default::default());
+        assert_eq!(events["Human"].len(), 1);
+        assert_eq!(events["Horse"].len(), 1);
+        assert_eq!(events["Human"], events["Horse"]);
+    }
+
+    fn write_kiss_slal(dir: &Path) -> PathBuf {
+        write_slal(
+            dir,
+            serde_json::json!([{
+                "name": "Kiss",
+                "tags": "Kissing",
+                "actors": [{ "type": "Female", "stages": [{ "id": "Kiss_A1_S1" }] }]
+            }]),
+        )
+    }
+
+    #[test]
+    fn slal_author_is_read_from_info_file() {
+        let dir = make_temp_dir();
+        let path = write_kiss_slal(&dir);
+        fs::write(dir.join(SLAL_INFO_FILE), r#"{ "author": "Someone" }"#).unwrap();
+        fs::write(dir.join("meta.json"), r#"{ "author": "Someone Else" }"#).unwrap();
+
+        let by_default = Project::from_slal(path.clone(), &Default::default());
+        let options = ImportOptions {
+            info_file: Some("meta.json".into()),
+            ..Default::default()
+        };
+        let by_name = Project::from_slal(path, &options);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(by_default.unwrap().pack_author, "Someone");
+        assert_eq!(by_name.unwrap().pack_author, "Someone Else");
+    }
+
+    #[test]
+    fn slal_import_survives_broken_info_file() {
+        let dir = make_temp_dir();
+        let path = write_kiss_slal(&dir);
+        let without_info = Project::from_slal(path.clone(), &Default::default());
+        fs::write(dir.join(SLAL_INFO_FILE), "not json").unwrap();
+
+        let with_broken_info = Project::from_slal(path, &Default::default());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(without_info.unwrap().pack_author, "Unknown");
+        let prjct = with_broken_info.unwrap();
+        assert_eq!(prjct.pack_author, "Unknown");
+        assert_eq!(prjct.scenes.len(), 1);
+    }
+
+    #[test]
+    fn missing_hkx_files_are_reported() {
+        let scene = SceneBuilder::new("Kiss")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Kiss_A1_S1")
+            .position_event(1, "Kiss_A2_S1")
+            .stage()
+            .position_event(0, "Kiss_A1_S2")
+            .position_event(1, "__BLANK__")
+            .build()
+            .unwrap();
+        let prjct = make_project(vec![scene]);
+        let dir = make_temp_dir();
+        let list = prjct.get_fnis_list_path(&dir, "Human").unwrap();
+        let folder = list.parent().unwrap();
+        fs::create_dir_all(folder).unwrap();
+        for event in ["Kiss_A1_S1", "Kiss_A1_S2"] {
+            fs::write(folder.join(format!("{}.hkx", event)), "").unwrap();
+        }
+
+        let missing = prjct.check_hkx_present(&dir, &Default::default());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(missing, vec![folder.join("Kiss_A2_S1.hkx")]);
+    }
+
+    #[test]
+    fn hkx_files_of_split_lists_belong_to_their_chunk() {
+        let prjct = make_project(vec![make_long_scene("Long")]);
+        let dir = make_temp_dir();
+        let options = BuildOptions {
+            split_threshold: Some(2),
+            ..Default::default()
+        };
+        let first = prjct.get_fnis_list_path(&dir, "Human").unwrap();
+        let first = first.parent().unwrap();
+        let second = make_fnis_list_path(&dir, "Human", "Test2").unwrap();
+        let second = second.parent().unwrap();
+
+        let manifest = prjct.hkx_manifest(&dir, &options);
+        assert_eq!(manifest.len(), 2);
+        assert_eq!(
+            manifest[first],
+            BTreeSet::from(["Long_A1_S1.hkx".to_string(), "Long_A1_S2.hkx".to_string()])
+        );
+        assert_eq!(
+            manifest[second],
+            BTreeSet::from(["Long_A1_S3.hkx".to_string()])
+        );
+
+        // a file in the folder of another chunk is not picked up by FNIS
+        fs::create_dir_all(first).unwrap();
+        for event in ["Long_A1_S1", "Long_A1_S2", "Long_A1_S3"] {
+            fs::write(first.join(format!("{}.hkx", event)), "").unwrap();
+        }
+        let missing = prjct.check_hkx_present(&dir, &options);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(missing, vec![second.join("Long_A1_S3.hkx")]);
+    }
+
+    #[test]
+    fn scene_name_prefix_is_only_encoded() {
+        let prjct = make_project(vec![make_scene("Kiss", "Human")]);
+        let options = BuildOptions {
+            scene_name_prefix: "[MyPack] ".into(),
+            ..Default::default()
+        };
+
+        let buf = prjct.registry_bytes(&options);
+        let decoded = Project::from_registry_bytes(&buf).unwrap();
+        assert_eq!(
+            decoded.scenes.values().next().unwrap().name,
+            "[MyPack] Kiss"
+        );
+        assert_eq!(prjct.scenes.values().next().unwrap().name, "Kiss");
+    }
+
+    #[test]
+    fn positions_without_race_fail_validation() {
+        let mut scene = make_scene("A", "Human");
+        scene.stages[0].positions[0].race = " ".into();
+        let id = scene.id.clone();
+        let mut prjct = make_project(vec![scene]);
+        assert!(prjct.diagnostics()[&id]
+            .iter()
+            .any(|d| d.contains("has no race")));
+        assert!(prjct.validate().is_err());
+        let result = prjct.build(PathBuf::from("unused"), &Default::default());
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+
+        prjct.get_scene_mut(&id).unwrap().stages[0].positions[0].race = "Not A Race".into();
+        assert!(prjct.validate().is_err());
+        prjct.get_scene_mut(&id).unwrap().has_warnings = true;
+        assert!(prjct.validate().is_ok());
+    }
+
+    #[test]
+    fn empty_race_files_are_written_on_request() {
+        let scene = SceneBuilder::new("Ride")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Horse")
+            .stage()
+            .position_event(0, "Ride_A1_S1")
+            .position_event(1, "__BLANK__")
+            .build()
+            .unwrap();
+        let prjct = make_project(vec![scene]);
+        let horse = |dir: &Path| prjct.get_fnis_list_path(dir, "Horse").unwrap();
+        let dir = make_temp_dir();
+        let lenient_dir = make_temp_dir();
+        let options = BuildOptions {
+            empty_race_files: true,
+            ..Default::default()
+        };
+
+        let result = prjct.build(dir.clone(), &options);
+        let lenient = prjct.build(lenient_dir.clone(), &Default::default());
+        let content = fs::read_to_string(horse(&dir));
+        let lenient_exists = horse(&lenient_dir).exists();
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&lenient_dir).unwrap();
+        assert!(result.is_ok() && lenient.is_ok());
+        assert_eq!(content.unwrap(), "");
+        assert!(!lenient_exists);
+    }
+
+    #[test]
+    fn isolated_export_uses_own_prefix() {
+        let scene = make_scene("Kiss", "Human");
+        let prjct = make_project(vec![scene.clone(), make_scene("Hug", "Human")]);
+        let dir = make_temp_dir();
+
+        let isolated = prjct.export_scene_isolated(&scene.id, dir.clone());
+        let registry = isolated
+            .as_ref()
+            .map(|isolated| isolated.get_registry_path(&dir).is_file())
+            .unwrap_or_default();
+        fs::remove_dir_all(&dir).unwrap();
+        let isolated = isolated.unwrap();
+        assert_ne!(isolated.prefix_hash, prjct.prefix_hash);
+        assert_ne!(isolated.pack_name, prjct.pack_name);
+        assert_eq!(isolated.scenes.len(), 1);
+        assert!(!prjct
+            .scenes
+            .contains_key(isolated.scenes.keys().next().unwrap()));
+        assert!(registry);
+    }
+
+    #[test]
+    fn load_progress_is_reported_per_scene() {
+        let mut prjct = make_project(vec![
+            make_scene("A", "Human"),
+            make_scene("B", "Human"),
+            make_scene("C", "Human"),
+        ]);
+        let dir = make_temp_dir();
+        let path = dir.join("Test.slsb.json");
+        prjct.write(path.clone()).unwrap();
+
+        let mut calls = vec![];
+        let loaded =
+            Project::from_file_with_progress(fs::File::open(&path).unwrap(), |n| calls.push(n));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.unwrap().scenes.len(), 3);
+        assert_eq!(calls, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn default_event_is_substituted() {
+        let scene = SceneBuilder::new("Idle")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Idle_A1_S1")
+            .position_event(1, "__DEFAULT__")
+            .position_event(2, "__BLANK__")
+            .build()
+            .unwrap();
+        let prjct = make_project(vec![scene]);
+        let options = BuildOptions {
+            default_event: Some("IdleStand".into()),
+            ..Default::default()
+        };
+
+        let events = prjct.make_fnis_events(&Default::default());
+        assert_eq!(events["Human"].len(), 1);
+        let events = prjct.make_fnis_events(&options);
+        let lines = &events["Human"];
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().any(|line| line.contains("IdleStand.hkx")));
+        assert!(!lines.iter().any(|line| line.contains("__")));
+    }
+
+    #[test]
+    fn creature_sex_is_normalized() {
+        let female = Sex {
+            male: false,
+            female: true,
+            futa: false,
+        };
+        let scene = SceneBuilder::new("Ride")
+            .actor(female.clone(), "Human")
+            .actor(female, "Horse")
+            .stage()
+            .position_event(0, "Ride_A1_S1")
+            .position_event(1, "Ride_A2_S1")
+            .build()
+            .unwrap();
+        let mut prjct = make_project(vec![scene]);
+
+        assert_eq!(prjct.normalize_creature_sex(Sex::default()), 1);
+        let positions = &prjct.scenes.values().next().unwrap().stages[0].positions;
+        assert!(positions[0].sex.female && !positions[0].sex.male);
+        assert!(positions[1].sex.male && !positions[1].sex.female);
+    }
+
+    #[test]
+    fn netch_requires_dragonborn() {
+        let prjct = make_project(vec![make_scene("A", "Human"), make_scene("B", "Netch")]);
+        let report = prjct.compatibility_report();
+        assert_eq!(
+            report.races,
+            BTreeSet::from(["Human".to_string(), "Netch".to_string()])
+        );
+        assert_eq!(
+            report.requirements,
+            BTreeSet::from(["Creature Framework".to_string(), "Dragonborn".to_string()])
+        );
+    }
+
+    #[test]
+    fn split_lists_are_known_to_path_helpers() {
+        let scene = SceneBuilder::new("Long")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Long_A1_S1")
+            .stage()
+            .position_event(0, "Long_A1_S2")
+            .stage()
+            .position_event(0, "Long_A1_S3")
+            .build()
+            .unwrap();
+        let prjct = make_project(vec![scene]);
+        let dir = make_temp_dir();
+        let options = BuildOptions {
+            split_threshold: Some(2),
+            ..Default::default()
+        };
+
+        let result = prjct.build(dir.clone(), &options);
+        let paths = prjct.fnis_output_paths(&dir, &options);
+        let lines: Vec<usize> = paths
+            .iter()
+            .map(|path| fs::read_to_string(path).map_or(0, |s| s.lines().count()))
+            .collect();
+        let folders = prjct.fnis_folders(&dir, &options);
+        let removed = prjct.clean_export(&dir, &options);
+        let remaining = paths.iter().any(|path| path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_ok());
+        let mut expected = vec![
+            prjct.get_fnis_list_path(&dir, "Human").unwrap(),
+            make_fnis_list_path(&dir, "Human", "Test2").unwrap(),
+        ];
+        expected.sort();
+        let mut sorted = paths.clone();
+        sorted.sort();
+        assert_eq!(sorted, expected);
+        assert_eq!(lines.iter().sum::<usize>(), 3);
+        assert_eq!(folders.len(), 2);
+        assert_eq!(removed.unwrap().len(), 3);
+        assert!(!remaining);
+    }
+
+    fn make_long_scene(name: &str) -> Scene {
+        SceneBuilder::new(name)
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, &format!("{}_A1_S1", name))
+            .stage()
+            .position_event(0, &format!("{}_A1_S2", name))
+            .stage()
+            .position_event(0, &format!("{}_A1_S3", name))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn loading_repairs_broken_graphs() {
+        let mut broken = make_long_scene("Broken");
+        let first = broken.stages[0].id.clone();
+        broken.graph.get_mut(&first).unwrap().dest = vec!["missing0".into()];
+        broken.graph.remove(&broken.stages[2].id);
+        let mut branching = make_long_scene("Branching");
+        let ids: Vec<NanoID> = branching.stages.iter().map(|s| s.id.clone()).collect();
+        branching.graph.get_mut(&ids[0]).unwrap().dest = vec![ids[2].clone(), ids[1].clone()];
+        branching.root = Default::default();
+        let mut prjct = make_project(vec![broken.clone(), branching.clone()]);
+        let dir = make_temp_dir();
+        let path = dir.join("Test.slsb.json");
+        prjct.write(path.clone()).unwrap();
+
+        let loaded = Project::from_file(fs::File::open(&path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap();
+        let repaired = loaded.get_scene(&broken.id).unwrap();
+        assert!(repaired.validate_graph().is_ok());
+        assert_eq!(repaired.root, first);
+        for pair in repaired.stages.windows(2) {
+            assert_eq!(repaired.graph[&pair[0].id].dest, vec![pair[1].id.clone()]);
+        }
+        assert!(repaired.graph[&repaired.stages[2].id].dest.is_empty());
+
+        // an unset root is valid while editing, the graph is kept as is
+        let kept = loaded.get_scene(&branching.id).unwrap();
+        assert!(kept.root.is_empty());
+        assert_eq!(
+            kept.graph[&ids[0]].dest,
+            vec![ids[2].clone(), ids[1].clone()]
+        );
+    }
+
+    #[test]
+    fn legacy_and_versioned_offset_files_load() {
+        let scene = make_scene("A", "Human");
+        let legacy: serde_yaml::Mapping = serde_yaml::from_str(&offset_yaml(&scene, 10.0)).unwrap();
+        let mut prjct = make_project(vec![scene.clone()]);
+        assert_eq!(prjct.apply_offset(legacy, Default::default()), Ok((1, 0)));
+        let x = |prjct: &Project| {
+            prjct.get_scene(&scene.id).unwrap().stages[0].positions[0]
+                .offset
+                .x
+        };
+        assert_eq!(x(&prjct), 10.0);
+
+        let mut exported = make_project(vec![scene.clone()]);
+        exported.get_scene_mut(&scene.id).unwrap().stages[0].positions[0]
+            .offset
+            .x = 20.0;
+        let versioned = exported.export_offset(&Default::default());
+        assert_eq!(versioned[&OFFSET_VERSION_KEY.into()], OFFSET_VERSION);
+        assert_eq!(
+            prjct.apply_offset(versioned, Default::default()),
+            Ok((1, 0))
+        );
+        assert_eq!(x(&prjct), 20.0);
+
+        let mut unknown = serde_yaml::Mapping::new();
+        unknown.insert(OFFSET_VERSION_KEY.into(), (OFFSET_VERSION + 1).into());
+        assert!(prjct.apply_offset(unknown, Default::default()).is_err());
+    }
+
+    #[test]
+    fn stage_id_reusing_scene_id_is_a_collision() {
+        let a = make_scene("A", "Human");
+        let mut b = make_scene("B", "Human");
+        let old_id = b.stages[0].id.clone();
+        let node = b.graph.remove(&old_id).unwrap();
+        b.stages[0].id = a.id.clone();
+        b.root = a.id.clone();
+        b.graph.insert(a.id.clone(), node);
+        let prjct = make_project(vec![a.clone(), b]);
+
+        assert_eq!(prjct.all_ids().len(), 6);
+        let collisions = prjct.find_id_collisions();
+        assert_eq!(collisions.len(), 1);
+        assert_eq!(
+            collisions[&a.id],
+            vec![IdKind::Scene, IdKind::Stage, IdKind::GraphNode]
+        );
+    }
+
+    #[test]
+    fn grouped_offsets_reimport() {
+        let mut a = make_long_scene("A");
+        a.stages[0].tags = vec!["Oral".into()];
+        a.stages[1].tags = vec!["Vaginal".into()];
+        let b = make_scene("B", "Human");
+        let mut prjct = make_project(vec![a.clone(), b.clone()]);
+        for (i, stage) in prjct
+            .scenes
+            .values_mut()
+            .flat_map(|scene| &mut scene.stages)
+            .enumerate()
+        {
+            stage.positions[0].offset.x = i as f32 + 1.0;
+        }
+
+        let grouped = prjct.export_offset_grouped(&Default::default());
+        let groups = grouped[&OFFSET_GROUPED_KEY.into()].as_mapping().unwrap();
+        assert_eq!(groups.len(), 3);
+        let mut imported = make_project(vec![a, b]);
+        assert_eq!(
+            imported.apply_offset(grouped, Default::default()),
+            Ok((2, 0))
+        );
+        for (id, scene) in &prjct.scenes {
+            let other = imported.get_scene(id).unwrap();
+            for (stage, other) in scene.stages.iter().zip(&other.stages) {
+                assert_eq!(stage.positions[0].offset.x, other.positions[0].offset.x);
+            }
+        }
+    }
+
+    #[test]
+    fn canonical_output_ignores_insertion_order() {
+        let a = make_long_scene("A");
+        let b = make_scene("B", "Wolf");
+        let first = make_project(vec![a.clone(), b.clone()]);
+        let mut reordered = a.clone();
+        reordered.stages.reverse();
+        let mut second = make_project(vec![b, reordered]);
+        second.prefix_hash = first.prefix_hash.clone();
+        let dir = make_temp_dir();
+
+        first.write_canonical(dir.join("first.json")).unwrap();
+        second.write_canonical(dir.join("second.json")).unwrap();
+        let first = fs::read(dir.join("first.json")).unwrap();
+        let second = fs::read(dir.join("second.json")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn slal_stage_sound_is_imported() {
+        let dir = make_temp_dir();
+        let path = write_slal(
+            &dir,
+            serde_json::json!([{
+                "name": "Loud",
+                "tags": "",
+                "actors": [{ "type": "Female", "stages": [{ "id": "Loud_A1_S1" }, { "id": "Loud_A1_S2" }] }],
+                "stage": [{ "number": 1, "sound": "Squishing" }]
+            }]),
+        );
+
+        let prjct = Project::from_slal(path, &Default::default());
+        fs::remove_dir_all(&dir).unwrap();
+        let prjct = prjct.unwrap();
+        let scene = prjct.scenes.values().next().unwrap();
+        assert_eq!(scene.stages[0].extra.sound, "");
+        assert_eq!(scene.stages[1].extra.sound, "Squishing");
+    }
+
+    #[test]
+    fn stage_sound_requires_registry_version_4() {
+        let mut scene = make_scene("A", "Human");
+        scene.stages[0].extra.sound = "Squishing".into();
+        let prjct = make_project(vec![scene]);
+        let bytes = |version| {
+            prjct.registry_bytes(&BuildOptions {
+                registry_version: version,
+                ..Default::default()
+            })
+        };
+
+        let default = prjct.registry_bytes(&Default::default());
+        assert_eq!(default, bytes(3));
+        let v4 = bytes(4);
+        assert_eq!(v4.len() - default.len(), 8 + "Squishing".len());
+        let sound = |buf: &[u8]| {
+            let decoded = Project::from_registry_bytes(buf).unwrap();
+            decoded.scenes.into_values().next().unwrap().stages[0]
+                .extra
+                .sound
+                .clone()
+        };
+        assert_eq!(sound(&default), "");
+        assert_eq!(sound(&v4), "Squishing");
+    }
+
+    #[test]
+    fn quick_test_export_only_removes_own_exports() {
+        let mut prjct = make_project(vec![make_scene("A", "Human")]);
+        prjct.pack_name = format!("Quick{}", nanoid!(NANOID_LENGTH, &NANOID_ALPHABET));
+        let temp_dir = std::env::temp_dir();
+        let prefix = format!("slsb-test-{}-", prjct.pack_name);
+        let old = temp_dir.join(format!("{}abcd1234", prefix));
+        let kept = [
+            temp_dir.join(format!("{}notes", prefix)),
+            temp_dir.join(format!("{}abcd1234-backup", prefix)),
+            temp_dir.join(format!("{}ABCD1234", prefix)),
+            temp_dir.join(format!("{}Extra-abcd1234", prefix)),
+        ];
+        for dir in kept.iter().chain(std::iter::once(&old)) {
+            fs::create_dir_all(dir).unwrap();
+        }
+
+        let result = prjct.quick_test_export(true);
+        let old_exists = old.exists();
+        let kept_exist: Vec<bool> = kept.iter().map(|dir| dir.exists()).collect();
+        let registry_exists = result
+            .as_ref()
+            .is_ok_and(|dir| prjct.get_registry_path(dir).exists());
+        for dir in kept.iter().chain(result.iter()) {
+            fs::remove_dir_all(dir).unwrap();
         }
+        let root = result.unwrap();
+        let name = root.file_name().unwrap().to_str().unwrap();
+        assert_eq!(name.len(), prefix.len() + NANOID_LENGTH);
+        assert!(registry_exists);
+        assert!(!old_exists);
+        assert_eq!(kept_exist, vec![true; 4]);
+    }
+
+    #[test]
+    fn offsets_round_trip_through_swapped_convention() {
+        let mut scene = make_scene("A", "Human");
+        scene.stages[0].positions[0].offset = Offset {
+            x: 10.0,
+            y: -5.0,
+            z: 1.0,
+            r: 90.0,
+        };
+        let mut prjct = make_project(vec![scene]);
+        let convention = OffsetConvention {
+            swap_xy: true,
+            ..Default::default()
+        };
+        let exported = prjct.export_offset(&BuildOptions {
+            offset_convention: convention,
+            ..Default::default()
+        });
+
+        let native = prjct.export_offset(&Default::default());
+        assert_ne!(exported, native);
+        let scene_id = prjct.scenes.keys().next().unwrap().clone();
+        prjct.scenes.get_mut(&scene_id).unwrap().stages[0].positions[0].offset = Offset::default();
+        assert_eq!(prjct.apply_offset(exported, convention), Ok((1, 0)));
+        let offset = &prjct.scenes[&scene_id].stages[0].positions[0].offset;
+        assert_eq!(
+            (offset.x, offset.y, offset.z, offset.r),
+            (10.0, -5.0, 1.0, 90.0)
+        );
+    }
+
+    #[test]
+    fn scenes_at_race_default_offsets_are_unaligned() {
+        let human = make_scene("Human", "Human");
+        let mut wolf = make_scene("Wolf", "Wolf");
+        wolf.stages[0].positions[0].offset = crate::racekeys::default_offset_for("Wolf");
+        let mut moved = make_scene("Moved", "Wolf");
+        moved.stages[0].positions[0].offset = Offset::default();
+        let prjct = make_project(vec![human, wolf, moved]);
+
+        let names: Vec<&str> = prjct
+            .scenes_without_offsets()
+            .iter()
+            .map(|scene| scene.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Human", "Wolf"]);
+    }
+
+    #[test]
+    fn registry_and_meshes_can_be_written_to_separate_roots() {
+        let prjct = make_project(vec![make_scene("A", "Human")]);
+        let dir = make_temp_dir();
+        let options = BuildOptions {
+            registry_root: Some(dir.join("registry")),
+            meshes_root: Some(dir.join("meshes")),
+            ..Default::default()
+        };
+
+        let result = prjct.build(dir.join("unused"), &options);
+        let registry = prjct.get_registry_path(&dir.join("registry")).exists();
+        let list = prjct
+            .get_fnis_list_path(&dir.join("meshes"), "Human")
+            .unwrap()
+            .exists();
+        let unused = dir.join("unused").exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_ok());
+        assert!(registry);
+        assert!(list);
+        assert!(!unused);
+    }
+
+    #[test]
+    fn only_edited_scenes_are_reported_as_changed() {
+        let mut prjct = make_project(vec![make_scene("A", "Human"), make_scene("B", "Human")]);
+        prjct.snapshot_hashes();
+        assert!(prjct.changed_scenes_since_save().is_empty());
+
+        let id = prjct
+            .scenes
+            .values()
+            .find(|scene| scene.name == "B")
+            .unwrap()
+            .id
+            .clone();
+        prjct.scenes.get_mut(&id).unwrap().stages[0].extra.fixed_len = 3.0;
+        assert_eq!(prjct.changed_scenes_since_save(), vec![id]);
+        prjct.snapshot_hashes();
+        assert!(prjct.changed_scenes_since_save().is_empty());
+    }
+
+    fn write_zip(path: &Path, name: &str, content: &[u8]) {
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        zip.start_file(name, Default::default()).unwrap();
+        zip.write_all(content).unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn archive_round_trips_project_and_registry() {
+        let mut prjct = make_project(vec![make_scene("A", "Human"), make_scene("B", "Wolf")]);
+        prjct.pack_author = "Someone".into();
+        let dir = make_temp_dir();
+        let full = dir.join("full.zip");
+        let registry = dir.join("registry.zip");
+        write_zip(
+            &full,
+            "Test.slsb.json",
+            &serde_json::to_vec(&prjct).unwrap(),
+        );
+        write_zip(
+            &registry,
+            "Test.slr",
+            &prjct.registry_bytes(&Default::default()),
+        );
+
+        let from_full = Project::import_archive(&full);
+        let from_registry = Project::import_archive(&registry);
+        fs::remove_dir_all(&dir).unwrap();
+        let canonical = |p: &Project| serde_json::to_string(&CanonicalProject(p)).unwrap();
+        let from_full = from_full.unwrap();
+        assert_eq!(from_full.pack_name, "Test");
+        assert_eq!(canonical(&from_full), canonical(&prjct));
+        let from_registry = from_registry.unwrap();
+        assert_eq!(from_registry.pack_author, "Someone");
+        let mut names: Vec<&str> = from_registry
+            .scenes
+            .values()
+            .map(|scene| scene.name.as_str())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["A", "B"]);
+    }
+
+    #[test]
+    fn scene_table_as_bbcode() {
+        let mut scene = make_scene("Kiss", "Human");
+        scene.stages[0].tags = vec!["Kissing".into(), " ".into(), "Loving".into()];
+        let mut broken = make_scene("Broken", "Human");
+        broken.has_warnings = true;
+        let prjct = make_project(vec![scene, make_scene("Hug", "Wolf"), broken]);
+
+        assert_eq!(
+            prjct.export_scene_table(TableFormat::BBCode),
+            "[table]\n\
+             [tr][th]Scene[/th][th]Actors[/th][th]Races[/th][th]Tags[/th][th]Stages[/th][/tr]\n\
+             [tr][td]Hug[/td][td]1[/td][td]Wolf[/td][td][/td][td]1[/td][/tr]\n\
+             [tr][td]Kiss[/td][td]1[/td][td]Human[/td][td]Kissing, Loving[/td][td]1[/td][/tr]\n\
+             [/table]\n"
+        );
+    }
+
+    #[test]
+    fn races_without_framework_are_listed() {
+        let human = make_scene("Human", "Human");
+        let wolf = make_scene("Wolf", "Wolf");
+        let horse = make_scene("Horse", "Horse");
+        let horse_id = horse.id.clone();
+        let prjct = make_project(vec![human, wolf, horse]);
+        let supported = HashSet::from(["Wolf".to_string()]);
+
+        assert_eq!(
+            prjct.unsupported_races(&supported),
+            vec![(horse_id, "Horse".to_string())]
+        );
+        assert_eq!(prjct.unsupported_races(&HashSet::new()).len(), 2);
+    }
+
+    #[test]
+    fn tags_are_counted_per_stage_across_scenes() {
+        let mut kiss = make_scene("Kiss", "Human");
+        kiss.stages[0].tags = vec!["Kissing".into(), " kissing ".into(), "Loving".into()];
+        let mut hug = make_scene("Hug", "Human");
+        hug.stages[0].tags = vec!["loving".into(), "".into()];
+        let prjct = make_project(vec![kiss, hug]);
+
+        assert_eq!(
+            prjct.all_tags(),
+            BTreeMap::from([("kissing".to_string(), 1), ("loving".to_string(), 2)])
+        );
+    }
+
+    #[test]
+    fn futa_races_are_configured_per_project() {
+        let mut scene = make_scene("Futa", "Horse");
+        scene.stages[0].positions[0].sex.futa = true;
+        let id = scene.id.clone();
+        let mut prjct = make_project(vec![scene]);
+        assert!(prjct.diagnostics()[&id][0].contains("is marked as futa"));
+        assert_eq!(prjct.self_check().warnings.len(), 1);
+
+        prjct.futa_races.push("Horse".into());
+        assert!(prjct.diagnostics().is_empty());
+        assert!(prjct.self_check().warnings.is_empty());
+
+        let loaded: Project = serde_json::from_str(
+            r#"{"pack_name":"Test","pack_author":"A","prefix_hash":"abcd","scenes":{}}"#,
+        )
+        .unwrap();
+        assert_eq!(loaded.futa_races, default_futa_races());
+    }
+
+    #[test]
+    fn loading_repairs_duplicate_stage_ids() {
+        let mut scene = make_long_scene("Duplicate");
+        let first = scene.stages[0].id.clone();
+        let last = scene.stages[2].id.clone();
+        scene.stages[1].id = first.clone();
+        scene.graph.retain(|id, _| id == &first || id == &last);
+        scene.graph.get_mut(&first).unwrap().dest = vec![last.clone()];
+        let mut prjct = make_project(vec![scene.clone()]);
+        let dir = make_temp_dir();
+        let path = dir.join("Test.slsb.json");
+        prjct.write(path.clone()).unwrap();
+
+        let loaded = Project::from_file(fs::File::open(&path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap();
+        let repaired = loaded.get_scene(&scene.id).unwrap();
+        assert_eq!(repaired.stages[0].id, first);
+        assert_ne!(repaired.stages[1].id, first);
+        assert!(repaired.validate_graph().is_ok());
+        assert!(loaded.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn written_offsets_can_be_imported() {
+        let scene = make_scene("A", "Human");
+        let mut prjct = make_project(vec![scene.clone()]);
+        prjct.get_scene_mut(&scene.id).unwrap().stages[0].positions[0]
+            .offset
+            .x = 12.0;
+        let dir = make_temp_dir();
+        prjct
+            .write_offset(
+                dir.join("Offset.yaml"),
+                &prjct.export_offset(&Default::default()),
+            )
+            .unwrap();
+
+        let mut target = make_project(vec![scene.clone()]);
+        let result = target.import_offset_dir(dir.clone(), &Default::default());
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result.unwrap(), (1, 0));
+        assert_eq!(
+            target.get_scene(&scene.id).unwrap().stages[0].positions[0]
+                .offset
+                .x,
+            12.0
+        );
+    }
+
+    #[test]
+    fn sex_naming_is_only_checked_when_configured() {
+        let mut scene = make_scene("Kiss", "Human");
+        scene.stages[0].positions[0].event = vec!["Kiss_M_S1".to_string()].into();
+        scene.stages[0].positions[0].sex.male = false;
+        scene.stages[0].positions[0].sex.female = true;
+        let id = scene.id.clone();
+        let mut prjct = make_project(vec![scene]);
+        assert!(prjct.diagnostics().is_empty());
+
+        prjct.sex_naming = Some(SexNaming::default());
+        assert!(prjct.diagnostics()[&id][0].contains("named for the opposite sex"));
+        assert_eq!(prjct.self_check().warnings.len(), 1);
+    }
+
+    #[test]
+    fn loading_normalizes_rotations() {
+        let scene = make_scene("A", "Human");
+        let mut prjct = make_project(vec![scene.clone()]);
+        prjct.get_scene_mut(&scene.id).unwrap().stages[0].positions[0]
+            .offset
+            .r = -90.0;
+        let dir = make_temp_dir();
+        let path = dir.join("Test.slsb.json");
+        prjct.write(path.clone()).unwrap();
+
+        let loaded = Project::from_file(fs::File::open(&path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap();
+        let position = &loaded.get_scene(&scene.id).unwrap().stages[0].positions[0];
+        assert_eq!(position.offset.r, 270.0);
+        assert!(loaded.diagnostics().is_empty());
     }
 }
@@ -1,9 +1,19 @@
+use log::{info, warn};
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, mem::size_of, vec};
+use std::{
+    collections::{HashMap, HashSet},
+    mem::size_of,
+    vec,
+};
+
+use crate::{furniture::Furniture, racekeys::default_offset_for};
 
+#[cfg(test)]
+use super::position::Sex;
 use super::{
-    serialize::{EncodeBinary, Offset},
+    position::{Position, SexNaming, CONTROL_EVENTS},
+    serialize::{ByteReader, EncodeBinary, Offset, REGISTRY_VERSION},
     stage::Stage,
     NanoID, NANOID_ALPHABET, NANOID_LENGTH,
 };
@@ -21,6 +31,8 @@ pub struct Scene {
 
     #[serde(default)] // addition 1.1
     pub has_warnings: bool,
+    #[serde(default)]
+    pub fnis_flags: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -37,6 +49,97 @@ pub struct Node {
     pub y: f32,
 }
 
+// Convenience to assemble a scene with a linear graph in code
+// Actors are declared first, every stage then receives one position per declared actor
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct SceneBuilder {
+    name: String,
+    actors: Vec<Position>,
+    stages: Vec<Stage>,
+}
+
+#[cfg(test)]
+impl SceneBuilder {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn actor(mut self, sex: Sex, race: &str) -> Self {
+        let mut position = Position::default();
+        position.sex = sex;
+        position.race = race.into();
+        self.actors.push(position);
+        self
+    }
+
+    pub fn stage(mut self) -> Self {
+        let mut stage = Stage::default();
+        stage.positions = self.actors.clone();
+        self.stages.push(stage);
+        self
+    }
+
+    // Set the event of the n-th actor in the most recent stage, repeated calls chain the events
+    pub fn position_event(mut self, n: usize, event: &str) -> Self {
+        if let Some(position) = self
+            .stages
+            .last_mut()
+            .and_then(|stage| stage.positions.get_mut(n))
+        {
+            let mut events = position.event.as_slice().to_vec();
+            events.push(event.into());
+            position.event = events.into();
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<Scene, String> {
+        if self.actors.is_empty() {
+            return Err(format!("Scene {} has no actors", self.name));
+        }
+        if self.stages.is_empty() {
+            return Err(format!("Scene {} has no stages", self.name));
+        }
+        for stage in &self.stages {
+            for (i, position) in stage.positions.iter().enumerate() {
+                if position.event.first().is_none() {
+                    return Err(format!(
+                        "Position {} in stage {} of scene {} has no event",
+                        i, stage.id, self.name
+                    ));
+                }
+            }
+        }
+        let mut scene = Scene::default();
+        scene.name = self.name;
+        scene.stages = self.stages;
+        scene.make_linear_graph();
+
+        Ok(scene)
+    }
+}
+
+impl FurnitureData {
+    pub fn read_byte(reader: &mut ByteReader) -> Result<Self, String> {
+        let mut ret = FurnitureData::default();
+        let furniture = Furniture::from_bits_retain(reader.read_u32()?);
+        if !furniture.is_empty() {
+            ret.furni_types = furniture
+                .iter_names()
+                .map(|(name, _)| name.to_string())
+                .collect();
+        }
+        ret.allow_bed = reader.read_u8()? != 0;
+        ret.offset = Offset::read_byte(reader)?;
+
+        Ok(ret)
+    }
+}
+
 impl EncodeBinary for FurnitureData {
     fn get_byte_size(&self) -> usize {
         1 + self.offset.get_byte_size() + size_of::<u32>()
@@ -71,6 +174,352 @@ impl Scene {
         None
     }
 
+    // Create a solo scene for every actor in this scene, each with a linear graph over the original stage order
+    // Fails if the stages do not all have the same number of positions
+    // Stage names, tags, extra data (fixed length, nav text) and the scene FNIS flags are copied as is into every solo scene
+    pub fn split_by_actor(&self) -> Result<Vec<Scene>, String> {
+        let count = self
+            .stages
+            .first()
+            .and_then(|stage| Some(stage.positions.len()))
+            .unwrap_or_default();
+        for stage in &self.stages {
+            if stage.positions.len() != count {
+                return Err(format!(
+                    "Stage {} has {} positions but the scene has {} actors",
+                    stage.id,
+                    stage.positions.len(),
+                    count
+                ));
+            }
+        }
+        let mut ret = vec![];
+        for n in 0..count {
+            let mut scene = Scene::default();
+            scene.name = format!("{} ({})", self.name, n + 1);
+            scene.furniture = self.furniture.clone();
+            scene.private = self.private;
+            scene.fnis_flags = self.fnis_flags.clone();
+            for stage in &self.stages {
+                let mut solo = Stage::default();
+                solo.name = stage.name.clone();
+                solo.positions = vec![stage.positions[n].clone()];
+                solo.tags = stage.tags.clone();
+                solo.extra = stage.extra.clone();
+                scene.stages.push(solo);
+            }
+            scene.make_linear_graph();
+            ret.push(scene);
+        }
+
+        Ok(ret)
+    }
+
+    // Replace the graph with a linear chain over the stages, in the order they are listed
+    pub fn make_linear_graph(&mut self) -> () {
+        self.graph.clear();
+        self.root = self
+            .stages
+            .first()
+            .and_then(|stage| Some(stage.id.clone()))
+            .unwrap_or_default();
+        let mut prev_id: Option<String> = None;
+        for stage in self.stages.iter().rev() {
+            let mut value = Node::default();
+            if let Some(id) = prev_id {
+                value.dest = vec![id];
+            }
+            self.graph.insert(stage.id.clone(), value);
+            prev_id = Some(stage.id.clone());
+        }
+    }
+
+    // Check that root and every edge of the graph point to a stage of this scene and every stage has a node
+    pub fn validate_graph(&self) -> Result<(), String> {
+        if self.get_stage(&self.root).is_none() {
+            return Err(format!("Root {} is not a stage of this scene", self.root));
+        }
+        self.validate_nodes()
+    }
+
+    // Check that every stage has a node and every node and edge of the graph point to a stage of this scene
+    pub fn validate_nodes(&self) -> Result<(), String> {
+        let ids: HashSet<&NanoID> = self.stages.iter().map(|stage| &stage.id).collect();
+        for id in &ids {
+            if !self.graph.contains_key(*id) {
+                return Err(format!("Stage {} has no node in the graph", id));
+            }
+        }
+        for (id, node) in &self.graph {
+            if !ids.contains(id) {
+                return Err(format!("Graph node {} is not a stage of this scene", id));
+            }
+            for dest in &node.dest {
+                if !ids.contains(dest) {
+                    return Err(format!("Stage {} links to unknown stage {}", id, dest));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Order stages as they are reached walking the graph breadth first from root, unreachable stages keep their order at the end
+    pub fn sort_stages_by_graph(&mut self) -> () {
+        let mut order: Vec<NanoID> = vec![];
+        let mut queue = std::collections::VecDeque::from([self.root.clone()]);
+        while let Some(id) = queue.pop_front() {
+            if order.contains(&id) || self.get_stage(&id).is_none() {
+                continue;
+            }
+            if let Some(node) = self.graph.get(&id) {
+                queue.extend(node.dest.iter().cloned());
+            }
+            order.push(id);
+        }
+        self.stages.sort_by_key(|stage| {
+            order
+                .iter()
+                .position(|id| id == &stage.id)
+                .unwrap_or(order.len())
+        });
+    }
+
+    // Insert a copy of the given stage right after it, the copy takes over all outgoing edges of the original
+    pub fn duplicate_stage(&mut self, id: &NanoID) -> Option<NanoID> {
+        let index = self.stages.iter().position(|stage| &stage.id == id)?;
+        let mut stage = self.stages[index].clone();
+        stage.id = nanoid!(NANOID_LENGTH, &NANOID_ALPHABET);
+        let new_id = stage.id.clone();
+        self.stages.insert(index + 1, stage);
+
+        let node = self.graph.entry(id.clone()).or_default();
+        let value = Node {
+            dest: std::mem::replace(&mut node.dest, vec![new_id.clone()]),
+            x: node.x + 50.0,
+            y: node.y,
+        };
+        self.graph.insert(new_id.clone(), value);
+
+        Some(new_id)
+    }
+
+    // No position in any stage has been moved from the default location of its race yet
+    pub fn is_unaligned(&self) -> bool {
+        self.stages
+            .iter()
+            .flat_map(|stage| &stage.positions)
+            .all(|position| !position.offset.differs(&default_offset_for(&position.race)))
+    }
+
+    // Stage id and index of every position which does not play any actual animation
+    pub fn unanimated_positions(&self) -> Vec<(NanoID, usize)> {
+        let mut ret = vec![];
+        for stage in &self.stages {
+            for (i, position) in stage.positions.iter().enumerate() {
+                if position.event.is_control() {
+                    ret.push((stage.id.clone(), i));
+                }
+            }
+        }
+
+        ret
+    }
+
+    // Assign a new id to every stage whose id is already used by a previous stage, returning the number of changed stages
+    // The renamed stage is linked like the original: it leads to the same stages and is reached from the same stages
+    pub fn dedup_stage_ids(&mut self) -> usize {
+        let mut seen = HashSet::new();
+        let mut count = 0;
+        for stage in &mut self.stages {
+            if seen.insert(stage.id.clone()) {
+                continue;
+            }
+            let id = nanoid!(NANOID_LENGTH, &NANOID_ALPHABET);
+            info!("Replacing duplicate stage id {} with {}", stage.id, id);
+            let mut value = Node::default();
+            if let Some(node) = self.graph.get(&stage.id) {
+                value.dest = node.dest.clone();
+                value.x = node.x + 50.0;
+                value.y = node.y;
+            }
+            for (_, node) in &mut self.graph {
+                if node.dest.contains(&stage.id) {
+                    node.dest.push(id.clone());
+                }
+            }
+            stage.id = id.clone();
+            self.graph.insert(id, value);
+            count += 1;
+        }
+
+        count
+    }
+
+    // FNIS options applied to every animation of this scene
+    pub fn get_fnis_flags(&self) -> Vec<String> {
+        self.fnis_flags
+            .split(',')
+            .map(|flag| flag.trim())
+            .filter(|flag| !flag.is_empty())
+            .map(|flag| flag.to_string())
+            .collect()
+    }
+
+    // Graphviz representation of the stage graph, edges are listed per node so cycles need no special care
+    pub fn to_dot(&self) -> String {
+        let mut ret = format!("digraph \"{}\" {{\n", self.name.replace('"', "\\\""));
+        for stage in &self.stages {
+            let label = if stage.name.is_empty() {
+                &stage.id
+            } else {
+                &stage.name
+            };
+            ret.push_str(&format!(
+                "    \"{}\" [label=\"{}\"{}];\n",
+                stage.id,
+                label.replace('"', "\\\""),
+                if stage.id == self.root {
+                    ", shape=doublecircle, style=filled"
+                } else {
+                    ""
+                }
+            ));
+        }
+        for stage in &self.stages {
+            if let Some(node) = self.graph.get(&stage.id) {
+                for dest in &node.dest {
+                    ret.push_str(&format!("    \"{}\" -> \"{}\";\n", stage.id, dest));
+                }
+            }
+        }
+        ret.push_str("}\n");
+
+        ret
+    }
+
+    // The registry data of this scene, as it is embedded in the registry file of its project
+    pub fn encode_bytes(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::with_capacity(self.get_byte_size());
+        self.write_byte(&mut buf);
+        buf
+    }
+
+    pub fn diagnostics(&self, futa_races: &[String]) -> Vec<String> {
+        let mut ret = self.validate_futa(futa_races);
+        ret.extend(self.validate_fixed_len());
+        if self.name.trim().is_empty() {
+            ret.push("Scene has no name".into());
+        }
+        // a scene without a root is fine while editing, but cannot be started once exported
+        if let Err(e) = self.validate_graph() {
+            ret.push(e);
+        }
+        let mut seen = HashSet::new();
+        for stage in &self.stages {
+            if !seen.insert(&stage.id) {
+                ret.push(format!("Stage id {} is used more than once", stage.id));
+            }
+            for (i, position) in stage.positions.iter().enumerate() {
+                if position.race.trim().is_empty() {
+                    ret.push(format!("Position {} in stage {} has no race", i, stage.id));
+                }
+                if !(0.0..360.0).contains(&position.offset.r) {
+                    ret.push(format!(
+                        "Position {} in stage {} has rotation {} outside of [0, 360)",
+                        i, stage.id, position.offset.r
+                    ));
+                }
+            }
+        }
+        // chained events are progressed by moving to the next stage, a single stage scene cannot do that
+        if self.stages.len() == 1 {
+            for (i, position) in self.stages[0].positions.iter().enumerate() {
+                if position.event.is_chain() {
+                    ret.push(format!(
+                        "Position {} has {} chained events but scene {} only has a single stage",
+                        i,
+                        position.event.as_slice().len(),
+                        self.name
+                    ));
+                }
+            }
+        }
+
+        ret
+    }
+
+    // Fixed length stages are timed by the a,Tn options on the last line of each chain, which requires that line to exist
+    pub fn validate_fixed_len(&self) -> Vec<String> {
+        let mut ret = vec![];
+        for stage in &self.stages {
+            if stage.extra.fixed_len <= 0.0 {
+                continue;
+            }
+            for (i, position) in stage.positions.iter().enumerate() {
+                let events = position.event.as_slice();
+                // placeholder positions are not registered, so there is nothing to time
+                if !events.is_empty() && position.event.is_control() {
+                    continue;
+                }
+                let timed = events.last().is_some_and(|event| {
+                    !event.trim().is_empty() && !CONTROL_EVENTS.contains(&event.as_str())
+                });
+                if !timed {
+                    ret.push(format!(
+                        "Position {} in fixed length stage {} has no event to carry the timer",
+                        i, stage.id
+                    ));
+                }
+            }
+        }
+
+        ret
+    }
+
+    pub fn validate_futa(&self, allowed_races: &[String]) -> Vec<String> {
+        let mut ret = vec![];
+        for stage in &self.stages {
+            for (i, position) in stage.positions.iter().enumerate() {
+                if position.sex.futa && !allowed_races.contains(&position.race) {
+                    ret.push(format!(
+                        "Position {} in stage {} is marked as futa but has race {}",
+                        i, stage.id, position.race
+                    ));
+                }
+            }
+        }
+
+        ret
+    }
+
+    // Positions whose sex contradicts the sex encoded in the name of their events, futa actors may play either role
+    pub fn validate_sex_naming(&self, naming: &SexNaming) -> Vec<String> {
+        let mut ret = vec![];
+        for stage in &self.stages {
+            for (i, position) in stage.positions.iter().enumerate() {
+                if position.sex.futa || position.event.is_control() {
+                    continue;
+                }
+                for event in position.event.as_slice() {
+                    let mismatch = match naming.expected_sex(event) {
+                        Some(sex) if sex.male => !position.sex.male,
+                        Some(_) => !position.sex.female,
+                        None => false,
+                    };
+                    if mismatch {
+                        ret.push(format!(
+                            "Position {} in stage {} plays event {} which is named for the opposite sex",
+                            i, stage.id, event
+                        ));
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+
     pub fn import_offset(&mut self, yaml_obj: &serde_yaml::Mapping) -> Result<(), String> {
         for (scene_id_v, scene_obj) in yaml_obj {
             let scene_id = scene_id_v
@@ -85,6 +534,17 @@ impl Scene {
                     "Expecting sequence in scene {} for stage {}",
                     id, stage.id
                 ))?;
+                // offsets authored for a different number of actors cannot be mapped onto this stage
+                if arg.len() != stage.positions.len() {
+                    warn!(
+                        "Offset for stage {} in scene {} has {} positions but the scene has {} actors, skipping",
+                        stage.id,
+                        id,
+                        arg.len(),
+                        stage.positions.len()
+                    );
+                    continue;
+                }
                 stage.import_offset(arg)?;
             }
         }
@@ -93,6 +553,39 @@ impl Scene {
     }
 }
 
+impl Scene {
+    pub fn read_byte(reader: &mut ByteReader, version: u8) -> Result<Self, String> {
+        let mut ret = Scene::default();
+        ret.id = reader.read_str(NANOID_LENGTH)?;
+        ret.name = reader.read_string()?;
+        let count = reader.read_u64()?;
+        let mut meta = vec![];
+        for _ in 0..count {
+            meta.push(Position::read_byte_meta(reader)?);
+        }
+        ret.root = reader.read_str(NANOID_LENGTH)?;
+        let count = reader.read_u64()?;
+        for _ in 0..count {
+            ret.stages.push(Stage::read_byte(reader, &meta, version)?);
+        }
+        // node locations are editor only data, the graph is laid out from scratch
+        let count = reader.read_u64()?;
+        for _ in 0..count {
+            let key = reader.read_str(NANOID_LENGTH)?;
+            let mut node = Node::default();
+            let dest_count = reader.read_u64()?;
+            for _ in 0..dest_count {
+                node.dest.push(reader.read_str(NANOID_LENGTH)?);
+            }
+            ret.graph.insert(key, node);
+        }
+        ret.furniture = FurnitureData::read_byte(reader)?;
+        ret.private = reader.read_u8()? != 0;
+
+        Ok(ret)
+    }
+}
+
 impl EncodeBinary for Scene {
     fn get_byte_size(&self) -> usize {
         let mut ret = self.name.len()
@@ -113,6 +606,10 @@ impl EncodeBinary for Scene {
     }
 
     fn write_byte(&self, buf: &mut Vec<u8>) -> () {
+        self.write_byte_version(buf, REGISTRY_VERSION)
+    }
+
+    fn write_byte_version(&self, buf: &mut Vec<u8>, version: u8) -> () {
         // id
         buf.extend_from_slice(self.id.as_bytes());
         // name
@@ -128,7 +625,7 @@ impl EncodeBinary for Scene {
         // stages
         buf.extend_from_slice(&(self.stages.len() as u64).to_be_bytes());
         for stage in &self.stages {
-            stage.write_byte(buf);
+            stage.write_byte_version(buf, version);
         }
         // graph
         buf.extend_from_slice(&(self.graph.len() as u64).to_be_bytes());
@@ -157,6 +654,7 @@ impl Default for Scene {
             furniture: Default::default(),
             private: Default::default(),
             has_warnings: Default::default(),
+            fnis_flags: Default::default(),
         }
     }
 }
@@ -180,3 +678,385 @@ impl Default for Node {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        define::{position::EventSpec, stage::AlignmentPreset},
+        racekeys::FUTA_RACES,
+    };
+
+    #[test]
+    fn single_stage_chain_is_reported() {
+        let scene = SceneBuilder::new("Chain")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Chain_A1_S1")
+            .position_event(0, "Chain_A1_S2")
+            .build()
+            .unwrap();
+        let diagnostics = scene.diagnostics(&FUTA_RACES.map(String::from));
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].contains("2 chained events"));
+    }
+
+    #[test]
+    fn multi_stage_chain_is_not_reported() {
+        let scene = SceneBuilder::new("Chain")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Chain_A1_S1")
+            .position_event(0, "Chain_A1_S2")
+            .stage()
+            .position_event(0, "Chain_A1_S3")
+            .build()
+            .unwrap();
+        assert!(scene.diagnostics(&FUTA_RACES.map(String::from)).is_empty());
+    }
+
+    #[test]
+    fn split_by_actor_creates_solo_scenes() {
+        let scene = SceneBuilder::new("Duo")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Wolf")
+            .stage()
+            .position_event(0, "Duo_A1_S1")
+            .position_event(1, "Duo_A2_S1")
+            .stage()
+            .position_event(0, "Duo_A1_S2")
+            .position_event(1, "Duo_A2_S2")
+            .build()
+            .unwrap();
+
+        let solos = scene.split_by_actor().unwrap();
+        assert_eq!(solos.len(), 2);
+        for (n, solo) in solos.iter().enumerate() {
+            assert!(solo.validate_graph().is_ok());
+            assert_ne!(solo.id, scene.id);
+            assert_eq!(solo.stages.len(), 2);
+            for (stage, original) in solo.stages.iter().zip(&scene.stages) {
+                assert_ne!(stage.id, original.id);
+                assert_eq!(stage.positions.len(), 1);
+                assert_eq!(stage.positions[0].event, original.positions[n].event);
+            }
+        }
+        assert_eq!(solos[1].stages[0].positions[0].race, "Wolf");
+    }
+
+    #[test]
+    fn split_by_actor_rejects_uneven_stages() {
+        let mut scene = SceneBuilder::new("Duo")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Duo_A1_S1")
+            .position_event(1, "Duo_A2_S1")
+            .stage()
+            .position_event(0, "Duo_A1_S2")
+            .position_event(1, "Duo_A2_S2")
+            .build()
+            .unwrap();
+        scene.stages[1].positions.pop();
+
+        assert!(scene.split_by_actor().is_err());
+    }
+
+    #[test]
+    fn futa_creature_is_flagged() {
+        let futa = Sex {
+            male: false,
+            female: true,
+            futa: true,
+        };
+        let scene = SceneBuilder::new("Futa")
+            .actor(futa.clone(), "Human")
+            .actor(futa, "Horse")
+            .stage()
+            .position_event(0, "Futa_A1_S1")
+            .position_event(1, "Futa_A2_S1")
+            .build()
+            .unwrap();
+
+        let warnings = scene.validate_futa(&FUTA_RACES.map(String::from));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Position 1"));
+        assert!(scene
+            .validate_futa(&["Human".into(), "Horse".into()])
+            .is_empty());
+    }
+
+    #[test]
+    fn builder_creates_linear_scene() {
+        let scene = SceneBuilder::new("Kiss")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Kiss_A1_S1")
+            .position_event(1, "Kiss_A2_S1")
+            .stage()
+            .position_event(0, "Kiss_A1_S2")
+            .position_event(1, "Kiss_A2_S2")
+            .build()
+            .unwrap();
+
+        assert_eq!(scene.name, "Kiss");
+        assert_eq!(scene.root, scene.stages[0].id);
+        assert_eq!(
+            scene.graph[&scene.stages[0].id].dest,
+            vec![scene.stages[1].id.clone()]
+        );
+        assert!(scene.graph[&scene.stages[1].id].dest.is_empty());
+        assert!(scene.validate_graph().is_ok());
+        assert!(scene.diagnostics(&FUTA_RACES.map(String::from)).is_empty());
+
+        let mut prjct = crate::define::project::Project::new();
+        prjct.save_scene(scene);
+        assert!(prjct.validate().is_ok());
+    }
+
+    #[test]
+    fn builder_rejects_positions_without_event() {
+        let result = SceneBuilder::new("Kiss")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Kiss_A1_S1")
+            .build();
+        assert!(result.is_err());
+        assert!(SceneBuilder::new("Empty").build().is_err());
+    }
+
+    #[test]
+    fn default_only_position_is_unanimated() {
+        let scene = SceneBuilder::new("Idle")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Idle_A1_S1")
+            .position_event(1, "__DEFAULT__")
+            .stage()
+            .position_event(0, "Idle_A1_S2")
+            .position_event(1, "Idle_A2_S2")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            scene.unanimated_positions(),
+            vec![(scene.stages[0].id.clone(), 1)]
+        );
+    }
+
+    #[test]
+    fn duplicate_stage_ids_are_detected_and_repaired() {
+        let mut scene = SceneBuilder::new("Dup")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Dup_A1_S1")
+            .stage()
+            .position_event(0, "Dup_A1_S2")
+            .stage()
+            .position_event(0, "Dup_A1_S3")
+            .build()
+            .unwrap();
+        let first = scene.stages[0].id.clone();
+        let last = scene.stages[2].id.clone();
+        scene.stages[1].id = last.clone();
+        scene.graph.retain(|id, _| id == &first || id == &last);
+        scene.graph.get_mut(&first).unwrap().dest = vec![last.clone()];
+        assert!(scene
+            .diagnostics(&FUTA_RACES.map(String::from))
+            .iter()
+            .any(|d| d.contains("used more than once")));
+
+        assert_eq!(scene.dedup_stage_ids(), 1);
+        let renamed = scene.stages[2].id.clone();
+        assert_ne!(renamed, last);
+        assert!(scene.diagnostics(&FUTA_RACES.map(String::from)).is_empty());
+        assert!(scene.validate_graph().is_ok());
+        assert_eq!(
+            scene.graph[&first].dest,
+            vec![last.clone(), renamed.clone()]
+        );
+        assert_eq!(scene.graph[&renamed].dest, scene.graph[&last].dest);
+    }
+
+    #[test]
+    fn split_by_actor_keeps_fnis_flags() {
+        let mut scene = SceneBuilder::new("Duo")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Duo_A1_S1")
+            .position_event(1, "Duo_A2_S1")
+            .build()
+            .unwrap();
+        scene.fnis_flags = "md, h".into();
+
+        for solo in scene.split_by_actor().unwrap() {
+            assert_eq!(solo.get_fnis_flags(), vec!["md", "h"]);
+        }
+    }
+
+    #[test]
+    fn offset_for_other_actor_count_is_skipped() {
+        let mut scene = SceneBuilder::new("Trio")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Trio_A1_S1")
+            .position_event(1, "Trio_A2_S1")
+            .position_event(2, "Trio_A3_S1")
+            .build()
+            .unwrap();
+        let id = scene.stages[0].id.clone();
+        let offsets = |count| {
+            let mut stage = Stage::from_count(count);
+            stage.apply_alignment_preset(AlignmentPreset::Behind);
+            let mut ret = serde_yaml::Mapping::new();
+            ret.insert(id.as_str().into(), stage.export_offset().into());
+            ret
+        };
+        let yaml = offsets(2);
+        assert!(scene.import_offset(&yaml).is_ok());
+        assert!(scene.is_unaligned());
+
+        let yaml = offsets(3);
+        assert!(scene.import_offset(&yaml).is_ok());
+        assert!(!scene.is_unaligned());
+    }
+
+    #[test]
+    fn dot_lists_every_edge_of_a_cycle() {
+        let mut scene = SceneBuilder::new("Loop")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Loop_A1_S1")
+            .stage()
+            .position_event(0, "Loop_A1_S2")
+            .stage()
+            .position_event(0, "Loop_A1_S3")
+            .build()
+            .unwrap();
+        let first = scene.stages[0].id.clone();
+        let last = scene.stages[2].id.clone();
+        scene.graph.get_mut(&last).unwrap().dest.push(first.clone());
+
+        let dot = scene.to_dot();
+        assert!(dot.starts_with("digraph \"Loop\" {"));
+        assert_eq!(dot.matches(" -> ").count(), 3);
+        assert!(dot.contains(&format!("\"{}\" -> \"{}\";", last, first)));
+        assert_eq!(dot.matches("doublecircle").count(), 1);
+    }
+
+    #[test]
+    fn unnamed_scene_is_flagged() {
+        let scene = SceneBuilder::new("  ")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Unnamed_A1_S1")
+            .build()
+            .unwrap();
+        assert_eq!(
+            scene.diagnostics(&FUTA_RACES.map(String::from)),
+            vec!["Scene has no name"]
+        );
+
+        let mut prjct = crate::define::project::Project::new();
+        prjct.save_scene(scene.clone());
+        assert_eq!(prjct.diagnostics()[&scene.id], vec!["Scene has no name"]);
+    }
+
+    #[test]
+    fn sex_naming_mismatch_is_flagged() {
+        let female = Sex {
+            male: false,
+            female: true,
+            futa: false,
+        };
+        let scene = SceneBuilder::new("Kiss")
+            .actor(female.clone(), "Human")
+            .actor(female, "Human")
+            .stage()
+            .position_event(0, "Kiss_F_S1")
+            .position_event(1, "Kiss_M_S1")
+            .build()
+            .unwrap();
+
+        let warnings = scene.validate_sex_naming(&SexNaming::default());
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Position 1"));
+        let naming = SexNaming {
+            male: vec!["*_Male".into()],
+            ..Default::default()
+        };
+        assert!(scene.validate_sex_naming(&naming).is_empty());
+    }
+
+    #[test]
+    fn duplicated_middle_stage_keeps_graph_valid() {
+        let mut scene = SceneBuilder::new("Dup")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Dup_A1_S1")
+            .stage()
+            .position_event(0, "Dup_A1_S2")
+            .stage()
+            .position_event(0, "Dup_A1_S3")
+            .build()
+            .unwrap();
+        let ids: Vec<NanoID> = scene.stages.iter().map(|s| s.id.clone()).collect();
+
+        let copy = scene.duplicate_stage(&ids[1]).unwrap();
+        assert!(scene.validate_graph().is_ok());
+        assert_eq!(scene.stages.len(), 4);
+        assert_eq!(scene.stages[2].id, copy);
+        assert_eq!(
+            scene.stages[2].positions[0].event,
+            scene.stages[1].positions[0].event
+        );
+        assert_eq!(scene.graph[&ids[1]].dest, vec![copy.clone()]);
+        assert_eq!(scene.graph[&copy].dest, vec![ids[2].clone()]);
+        assert!(scene.duplicate_stage(&"missing0".into()).is_none());
+    }
+
+    #[test]
+    fn fixed_length_stage_without_event_is_reported() {
+        let mut scene = SceneBuilder::new("Fixed")
+            .actor(Sex::default(), "Human")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Fixed_A1_S1")
+            .position_event(1, "Fixed_A2_S1")
+            .build()
+            .unwrap();
+        assert!(scene.validate_fixed_len().is_empty());
+
+        scene.stages[0].extra.fixed_len = 5.0;
+        assert!(scene.validate_fixed_len().is_empty());
+        scene.stages[0].positions[1].event = EventSpec::Chain(vec![]);
+        let errors = scene.validate_fixed_len();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Position 1 "));
+
+        scene.stages[0].positions[1].event = EventSpec::Single("__BLANK__".into());
+        assert!(scene.validate_fixed_len().is_empty());
+    }
+
+    #[test]
+    fn broken_graph_is_reported() {
+        let mut scene = SceneBuilder::new("Graph")
+            .actor(Sex::default(), "Human")
+            .stage()
+            .position_event(0, "Graph_A1_S1")
+            .build()
+            .unwrap();
+        assert!(scene.diagnostics(&FUTA_RACES.map(String::from)).is_empty());
+
+        scene.root = Default::default();
+        let diagnostics = scene.diagnostics(&FUTA_RACES.map(String::from));
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].contains("is not a stage of this scene"));
+    }
+}
@@ -2,9 +2,81 @@ use std::mem::size_of;
 
 use serde::{Deserialize, Serialize};
 
+use super::position::EventSpec;
+
+// Registry version written by default and the range of versions which can be written
+// Version 2 predates the per position schlong byte, which was added with version 3
+// Versions above the default are opt-in as they require a SexLab build which reads the additional stage data:
+// 4 adds the stage sound, 5 the open mouth flag
+pub const REGISTRY_VERSION: u8 = 3;
+pub const MIN_REGISTRY_VERSION: u8 = 2;
+pub const MAX_REGISTRY_VERSION: u8 = 5;
+
 pub trait EncodeBinary {
     fn get_byte_size(&self) -> usize;
     fn write_byte(&self, buf: &mut Vec<u8>) -> ();
+
+    // Types whose layout depends on the registry version override this
+    fn write_byte_version(&self, buf: &mut Vec<u8>, _version: u8) -> () {
+        self.write_byte(buf)
+    }
+}
+
+// Reads back the values written by EncodeBinary, in the order they were written
+pub struct ByteReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() - self.pos < len {
+            return Err(format!(
+                "Unexpected end of registry data at byte {}, expected {} more bytes",
+                self.pos, len
+            ));
+        }
+        let ret = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(ret)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    // floats are written as i32 with a precision of 1/1000
+    pub fn read_f32(&mut self) -> Result<f32, String> {
+        let value = i32::from_be_bytes(self.read_bytes(4)?.try_into().unwrap());
+        Ok(value as f32 / 1000.0)
+    }
+
+    pub fn read_str(&mut self, len: usize) -> Result<String, String> {
+        let bytes = self.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|e| e.to_string())
+    }
+
+    // a string prefixed by its length
+    pub fn read_string(&mut self) -> Result<String, String> {
+        let len = self.read_u64()? as usize;
+        self.read_str(len)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -15,6 +87,85 @@ pub struct Offset {
     pub r: f32,
 }
 
+// offsets are encoded with a precision of 1/1000, anything below is not considered a difference
+pub const OFFSET_EPSILON: f32 = 0.001;
+
+impl Offset {
+    // rotation mapped into [0, 360)
+    pub fn normalized_rotation(&self) -> f32 {
+        self.r.rem_euclid(360.0)
+    }
+
+    pub fn normalize(&mut self) -> () {
+        self.r = self.normalized_rotation();
+    }
+
+    pub fn differs(&self, other: &Offset) -> bool {
+        (self.x - other.x).abs() >= OFFSET_EPSILON
+            || (self.y - other.y).abs() >= OFFSET_EPSILON
+            || (self.z - other.z).abs() >= OFFSET_EPSILON
+            || (self.r - other.r).abs() >= OFFSET_EPSILON
+    }
+}
+
+// Axis convention of offset files written by other tools
+// Offsets are stored in the convention of the registry, other conventions only apply to offset files
+// The other convention is mapped onto it by first swapping x and y, then negating the selected components
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct OffsetConvention {
+    pub swap_xy: bool,
+    pub negate_x: bool,
+    pub negate_y: bool,
+    pub negate_r: bool,
+}
+
+impl OffsetConvention {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    // Map an offset read from a file in this convention onto the native convention
+    pub fn import(self, offset: &Offset) -> Offset {
+        let (x, y) = match self.swap_xy {
+            true => (offset.y, offset.x),
+            false => (offset.x, offset.y),
+        };
+        Offset {
+            x: if self.negate_x { -x } else { x },
+            y: if self.negate_y { -y } else { y },
+            z: offset.z,
+            r: if self.negate_r { -offset.r } else { offset.r },
+        }
+    }
+
+    // Map a native offset into this convention for writing it to a file
+    pub fn export(self, offset: &Offset) -> Offset {
+        let x = if self.negate_x { -offset.x } else { offset.x };
+        let y = if self.negate_y { -offset.y } else { offset.y };
+        let (x, y) = match self.swap_xy {
+            true => (y, x),
+            false => (x, y),
+        };
+        Offset {
+            x,
+            y,
+            z: offset.z,
+            r: if self.negate_r { -offset.r } else { offset.r },
+        }
+    }
+}
+
+impl Offset {
+    pub fn read_byte(reader: &mut ByteReader) -> Result<Self, String> {
+        Ok(Self {
+            x: reader.read_f32()?,
+            y: reader.read_f32()?,
+            z: reader.read_f32()?,
+            r: reader.read_f32()?,
+        })
+    }
+}
+
 impl EncodeBinary for Offset {
     fn get_byte_size(&self) -> usize {
         size_of::<Offset>()
@@ -27,7 +178,7 @@ impl EncodeBinary for Offset {
         buf.extend_from_slice(&y_.to_be_bytes());
         let z_ = (self.z * 1000.0).round() as i32;
         buf.extend_from_slice(&z_.to_be_bytes());
-        let r_ = (self.r * 1000.0).round() as i32;
+        let r_ = (self.normalized_rotation() * 1000.0).round() as i32;
         buf.extend_from_slice(&r_.to_be_bytes());
     }
 }
@@ -83,36 +234,49 @@ pub fn map_race_to_folder(race: &str) -> Result<String, ()> {
     }
 }
 
+// Options which may be passed to FNIS through custom flags
+pub const FNIS_OPTIONS: [&str; 8] = ["a", "h", "k", "bsa", "md", "rd", "st", "Tn"];
+
 pub fn make_fnis_lines(
-    events: &Vec<String>,
+    events: &EventSpec,
     hash: &str,
     fixed_len: bool,
+    flags: &[String],
     anim_obj: &Vec<String>,
 ) -> Vec<String> {
-    if events.len() == 1 {
-        return vec![make_fnis_line(
+    let make_options = |timed: bool| {
+        let mut options = flags.to_vec();
+        if timed {
+            for option in ["a", "Tn"] {
+                if !options.iter().any(|x| x == option) {
+                    options.push(option.into());
+                }
+            }
+        }
+        options.join(",")
+    };
+    match events {
+        EventSpec::Single(event) => vec![make_fnis_line(
             "b",
-            &events[0],
-            hash,
-            if fixed_len { "a,Tn" } else { "" },
-            anim_obj,
-        )];
-    }
-    let mut ret = vec![];
-    for (i, event) in events.iter().enumerate() {
-        ret.push(make_fnis_line(
-            if i == 0 { "s" } else { "+" },
             event,
             hash,
-            if fixed_len && i == events.len() - 1 {
-                "a,Tn"
-            } else {
-                ""
-            },
+            &make_options(fixed_len),
             anim_obj,
-        ));
+        )],
+        EventSpec::Chain(events) => {
+            let mut ret = vec![];
+            for (i, event) in events.iter().enumerate() {
+                ret.push(make_fnis_line(
+                    if i == 0 { "s" } else { "+" },
+                    event,
+                    hash,
+                    &make_options(fixed_len && i == events.len() - 1),
+                    anim_obj,
+                ));
+            }
+            ret
+        }
     }
-    ret
 }
 
 fn make_fnis_line(
@@ -142,3 +306,70 @@ fn make_fnis_line(
             .fold(String::from(""), |acc, x| format!("{} {}", acc, x))
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chain_lines_start_with_s_and_continue_with_plus() {
+        let events = EventSpec::Chain(vec!["Kiss_A1_S1".into(), "Kiss_A1_S2".into()]);
+        let lines = make_fnis_lines(&events, "abcd", false, &[], &vec![]);
+        assert_eq!(
+            lines,
+            vec![
+                "s abcdKiss_A1_S1 Kiss_A1_S1.hkx",
+                "+ abcdKiss_A1_S2 Kiss_A1_S2.hkx"
+            ]
+        );
+    }
+
+    #[test]
+    fn single_line_starts_with_b() {
+        let events = EventSpec::Single("Kiss_A1_S1".into());
+        let lines = make_fnis_lines(&events, "abcd", false, &[], &vec![]);
+        assert_eq!(lines, vec!["b abcdKiss_A1_S1 Kiss_A1_S1.hkx"]);
+    }
+
+    #[test]
+    fn offset_convention_round_trips() {
+        let convention = OffsetConvention {
+            swap_xy: true,
+            negate_x: true,
+            negate_y: false,
+            negate_r: true,
+        };
+        let native = Offset {
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+            r: 45.0,
+        };
+
+        let foreign = convention.export(&native);
+        assert_eq!(
+            (foreign.x, foreign.y, foreign.z, foreign.r),
+            (2.0, -1.0, 3.0, -45.0)
+        );
+        let back = convention.import(&foreign);
+        assert!(!back.differs(&native));
+        assert!(OffsetConvention::default().is_identity());
+        assert!(!convention.is_identity());
+    }
+
+    #[test]
+    fn negative_rotation_is_normalized() {
+        let mut offset = Offset {
+            r: -90.0,
+            ..Default::default()
+        };
+        let mut buf = vec![];
+        offset.write_byte(&mut buf);
+        assert_eq!(buf[12..16], 270_000i32.to_be_bytes());
+
+        offset.normalize();
+        assert_eq!(offset.r, 270.0);
+        offset.r = 720.0;
+        assert_eq!(offset.normalized_rotation(), 0.0);
+    }
+}
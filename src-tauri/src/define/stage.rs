@@ -3,7 +3,11 @@ use std::{mem::size_of, vec};
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
 
-use super::{position::Position, serialize::EncodeBinary, NanoID, NANOID_ALPHABET, NANOID_LENGTH};
+use super::{
+    position::Position,
+    serialize::{ByteReader, EncodeBinary, Offset, REGISTRY_VERSION},
+    NanoID, NANOID_ALPHABET, NANOID_LENGTH,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Stage {
@@ -19,6 +23,20 @@ pub struct Stage {
 pub struct Extra {
     pub fixed_len: f32,
     pub nav_text: String,
+    // sound played alongside the stage, empty if none, registry version 4+
+    #[serde(default)]
+    pub sound: String,
+    // keep the mouth of all actors open for the duration of the stage, registry version 5+
+    #[serde(default)]
+    pub open_mouth: bool,
+}
+
+// Common arrangements of actors relative to the first actor
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum AlignmentPreset {
+    Facing,
+    Behind,
+    Stacked,
 }
 
 impl Stage {
@@ -29,6 +47,41 @@ impl Stage {
         ret
     }
 
+    pub fn export_offset(&self) -> serde_yaml::Sequence {
+        self.positions
+            .iter()
+            .map(|position| position.export_offset().into())
+            .collect()
+    }
+
+    // Overwrite the offsets of all positions with a rough starting alignment, the first actor stays in place
+    pub fn apply_alignment_preset(&mut self, preset: AlignmentPreset) -> () {
+        for (i, position) in self.positions.iter_mut().enumerate() {
+            let n = i as f32;
+            position.offset = match preset {
+                _ if i == 0 => Offset::default(),
+                AlignmentPreset::Facing => Offset {
+                    x: 0.0,
+                    y: 40.0 * n,
+                    z: 0.0,
+                    r: 180.0,
+                },
+                AlignmentPreset::Behind => Offset {
+                    x: 0.0,
+                    y: -30.0 * n,
+                    z: 0.0,
+                    r: 0.0,
+                },
+                AlignmentPreset::Stacked => Offset {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 15.0 * n,
+                    r: 0.0,
+                },
+            };
+        }
+    }
+
     pub fn import_offset(&mut self, yaml_obj: &serde_yaml::Sequence) -> Result<(), String> {
         let list: Vec<_> = yaml_obj
             .iter()
@@ -55,13 +108,59 @@ impl Stage {
     }
 }
 
+impl Stage {
+    // meta holds the position of every actor as read by Position::read_byte_meta
+    pub fn read_byte(
+        reader: &mut ByteReader,
+        meta: &Vec<Position>,
+        version: u8,
+    ) -> Result<Self, String> {
+        let mut ret = Stage {
+            positions: vec![],
+            ..Default::default()
+        };
+        ret.id = reader.read_str(NANOID_LENGTH)?;
+        let count = reader.read_u64()? as usize;
+        if count != meta.len() {
+            return Err(format!(
+                "Stage {} has {} positions but the scene has {} actors",
+                ret.id,
+                count,
+                meta.len()
+            ));
+        }
+        for template in meta {
+            let mut position = template.clone();
+            position.read_byte(reader, version)?;
+            ret.positions.push(position);
+        }
+        ret.extra.fixed_len = reader.read_f32()?;
+        ret.extra.nav_text = reader.read_string()?;
+        let count = reader.read_u64()?;
+        for _ in 0..count {
+            ret.tags.push(reader.read_string()?);
+        }
+        if version >= 4 {
+            ret.extra.sound = reader.read_string()?;
+        }
+        if version >= 5 {
+            ret.extra.open_mouth = reader.read_u8()? != 0;
+        }
+
+        Ok(ret)
+    }
+}
+
 impl EncodeBinary for Stage {
     fn get_byte_size(&self) -> usize {
         let mut ret = NANOID_LENGTH
             + 3 * size_of::<u64>()
             + self.tags.len() * size_of::<u64>()
             + size_of::<i32>()
-            + self.extra.nav_text.len();
+            + self.extra.nav_text.len()
+            + size_of::<u64>()
+            + self.extra.sound.len()
+            + size_of::<u8>();
         for tag in &self.tags {
             ret += tag.len() + 1;
         }
@@ -73,12 +172,16 @@ impl EncodeBinary for Stage {
     }
 
     fn write_byte(&self, buf: &mut Vec<u8>) -> () {
+        self.write_byte_version(buf, REGISTRY_VERSION)
+    }
+
+    fn write_byte_version(&self, buf: &mut Vec<u8>, version: u8) -> () {
         // id
         buf.extend_from_slice(self.id.as_bytes());
         // positions
         buf.extend_from_slice(&(self.positions.len() as u64).to_be_bytes());
         for position in &self.positions {
-            position.write_byte(buf);
+            position.write_byte_version(buf, version);
         }
         // extra
         let l_ = (self.extra.fixed_len * 1000.0).round() as i32;
@@ -93,6 +196,15 @@ impl EncodeBinary for Stage {
             buf.extend_from_slice(&(tmp.len() as u64).to_be_bytes());
             buf.extend_from_slice(tmp.as_bytes());
         }
+        // sound
+        if version >= 4 {
+            buf.extend_from_slice(&(self.extra.sound.len() as u64).to_be_bytes());
+            buf.extend_from_slice(self.extra.sound.as_bytes());
+        }
+        // facial
+        if version >= 5 {
+            buf.push(self.extra.open_mouth as u8);
+        }
     }
 }
 
@@ -113,3 +225,43 @@ impl Default for Stage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn behind_preset_moves_second_actor_back() {
+        let mut stage = Stage::from_count(2);
+        stage.apply_alignment_preset(AlignmentPreset::Behind);
+
+        let first = &stage.positions[0].offset;
+        let second = &stage.positions[1].offset;
+        assert!(!first.differs(&Offset::default()));
+        assert!(second.y < first.y);
+        assert_eq!(second.x, first.x);
+        assert_eq!(second.r, first.r);
+    }
+
+    #[test]
+    fn open_mouth_is_only_written_from_version_5() {
+        let mut stage = Stage::from_count(1);
+        stage.positions[0].event = vec!["Kiss_A1_S1".to_string()].into();
+        stage.extra.open_mouth = true;
+        let bytes = |version| {
+            let mut buf = vec![];
+            stage.write_byte_version(&mut buf, version);
+            buf
+        };
+        let read = |buf: &[u8], version| {
+            Stage::read_byte(&mut ByteReader::new(buf), &stage.positions, version).unwrap()
+        };
+
+        let mut default = vec![];
+        stage.write_byte(&mut default);
+        assert_eq!(default, bytes(3));
+        assert_eq!(bytes(5).len(), bytes(4).len() + 1);
+        assert!(!read(&bytes(4), 4).extra.open_mouth);
+        assert!(read(&bytes(5), 5).extra.open_mouth);
+    }
+}
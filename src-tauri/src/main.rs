@@ -6,11 +6,20 @@ mod define;
 mod furniture;
 mod racekeys;
 
-use define::{position::Position, project::Project, scene::Scene, stage::Stage, NanoID};
-use log::{error, info};
+use define::{
+    position::{Position, Sex, SexNaming},
+    project::{
+        BuildOptions, CompatReport, HealthReport, IdKind, ImportOptions, Project, TableFormat,
+    },
+    scene::Scene,
+    stage::{AlignmentPreset, Stage},
+    NanoID,
+};
+use log::{error, info, warn};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
     path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -65,6 +74,8 @@ const MAIN_WINDOW: &str = "main_window";
 const NEW_PROJECT: &str = "new_prjct";
 const OPEN_PROJECT: &str = "open_prjct";
 const OPEN_SLAL: &str = "open_slal";
+const IMPORT_PORTABLE: &str = "import_portable";
+const IMPORT_ARCHIVE: &str = "import_archive";
 const DARKMODE: &str = "darkmode";
 
 fn main() {
@@ -76,10 +87,36 @@ fn main() {
             create_blank_scene,
             save_scene,
             delete_scene,
+            split_scene_by_actor,
+            unanimated_positions,
+            scene_bytes,
+            scene_to_dot,
+            duplicate_stage,
             open_stage_editor,
             open_stage_editor_from,
             stage_save_and_close,
+            apply_alignment_preset,
             make_position,
+            normalize_creature_sex,
+            get_default_race,
+            set_default_race,
+            set_sex_naming,
+            find_id_collisions,
+            diagnostics,
+            self_check,
+            compatibility_report,
+            scenes_without_offsets,
+            changed_scenes_since_save,
+            export_scene_table,
+            unsupported_races,
+            all_tags,
+            quick_test_export,
+            export_scene_isolated,
+            fnis_output_paths,
+            fnis_folders,
+            hkx_manifest,
+            check_hkx_present,
+            clean_export,
             mark_as_edited,
             get_in_darkmode
         ])
@@ -127,10 +164,28 @@ fn main() {
                         .add_item(
                             CustomMenuItem::new(OPEN_SLAL, "Import SLAL File")
                         )
+                        .add_item(
+                            CustomMenuItem::new(IMPORT_PORTABLE, "Import Portable...")
+                        )
+                        .add_item(
+                            CustomMenuItem::new(IMPORT_ARCHIVE, "Import Archive...")
+                        )
                         .add_native_item(MenuItem::Separator)
                         .add_item(
                             CustomMenuItem::new("import_offset", "Import Offset.yaml")
                         )
+                        .add_item(
+                            CustomMenuItem::new("import_offset_dir", "Import Offset Folder")
+                        )
+                        .add_item(
+                            CustomMenuItem::new("export_offset", "Export Offset.yaml")
+                        )
+                        .add_item(
+                            CustomMenuItem::new("export_offset_grouped", "Export Offset.yaml by Tag")
+                        )
+                        .add_item(
+                            CustomMenuItem::new("export_offset_delta", "Export Changed Offsets...")
+                        )
                         .add_native_item(MenuItem::Separator)
                         .add_item(
                             CustomMenuItem::new("save", "Save")
@@ -144,6 +199,12 @@ fn main() {
                             CustomMenuItem::new("build", "Export")
                                 .accelerator("cmdOrControl+B"),
                         )
+                        .add_item(
+                            CustomMenuItem::new("export_portable", "Export Portable...")
+                        )
+                        .add_item(
+                            CustomMenuItem::new("export_canonical", "Export Canonical Snapshot...")
+                        )
                         .add_native_item(MenuItem::Quit)
                 ))
                 .add_submenu(Submenu::new(
@@ -167,7 +228,7 @@ fn main() {
             }));
             let menu_handle = app.app_handle();
             window.on_menu_event(move |event| match event.menu_item_id() {
-                NEW_PROJECT | OPEN_PROJECT | OPEN_SLAL => {
+                NEW_PROJECT | OPEN_PROJECT | OPEN_SLAL | IMPORT_PORTABLE | IMPORT_ARCHIVE => {
                     let eventid = event.menu_item_id().to_string();
                     let window = menu_handle.get_window(MAIN_WINDOW).unwrap();
                     if get_edited() {
@@ -204,6 +265,18 @@ fn main() {
                         error!("{}", e);
                     }
                 }
+                "export_portable" => {
+                    let r = PROJECT.lock().unwrap().export_portable();
+                    if let Err(e) = r {
+                        error!("{}", e);
+                    }
+                }
+                "export_canonical" => {
+                    let r = PROJECT.lock().unwrap().export_canonical();
+                    if let Err(e) = r {
+                        error!("{}", e);
+                    }
+                }
                 DARKMODE => {
                     let window = menu_handle.get_window(MAIN_WINDOW).unwrap();
                     let menu = window.menu_handle().get_item(DARKMODE);
@@ -234,6 +307,44 @@ fn main() {
                         error!("{}", err);
                     }
                 }
+                "import_offset_dir" => {
+                    if let Some(dir) = tauri::api::dialog::blocking::FileDialogBuilder::new().pick_folder() {
+                        let mut prjct = PROJECT.lock().unwrap();
+                        if let Err(err) = prjct.import_offset_dir(dir, &Default::default()) {
+                            error!("{}", err);
+                        }
+                    }
+                }
+                "export_offset" => {
+                    let prjct = PROJECT.lock().unwrap();
+                    if let Err(err) = prjct.save_offset(&prjct.export_offset(&Default::default())) {
+                        error!("{}", err);
+                    }
+                }
+                "export_offset_grouped" => {
+                    let prjct = PROJECT.lock().unwrap();
+                    if let Err(err) = prjct.save_offset(&prjct.export_offset_grouped(&Default::default())) {
+                        error!("{}", err);
+                    }
+                }
+                "export_offset_delta" => {
+                    // offsets are compared against a previously saved state of the project
+                    if let Some(path) = tauri::api::dialog::blocking::FileDialogBuilder::new()
+                        .add_filter("SL Project File", vec!["slsb.json"].as_slice())
+                        .pick_file()
+                    {
+                        let prjct = PROJECT.lock().unwrap();
+                        let r = std::fs::File::open(path)
+                            .map_err(|e| e.to_string())
+                            .and_then(Project::from_file)
+                            .and_then(|baseline| {
+                                prjct.save_offset(&prjct.export_offset_delta(&baseline, &Default::default()))
+                            });
+                        if let Err(err) = r {
+                            error!("{}", err);
+                        }
+                    }
+                }
                 _ => {error!("Unrecognized command: {}", event.menu_item_id())}
             });
             window.on_window_event(|event| match event {
@@ -269,6 +380,8 @@ fn reload_project(reload_type: &str, window: &tauri::Window) {
         }
         OPEN_PROJECT => prjct.load_project(),
         OPEN_SLAL => prjct.load_slal(),
+        IMPORT_PORTABLE => prjct.import_portable(),
+        IMPORT_ARCHIVE => prjct.load_archive(),
         _ => Err(format!("Invalid reload type: {}", reload_type)),
     };
     if let Err(e) = result {
@@ -283,7 +396,20 @@ fn reload_project(reload_type: &str, window: &tauri::Window) {
     }
 
     window.emit("on_project_update", &prjct.scenes).unwrap();
-    set_edited(reload_type == OPEN_SLAL);
+    set_edited(
+        reload_type == OPEN_SLAL || reload_type == IMPORT_PORTABLE || reload_type == IMPORT_ARCHIVE,
+    );
+}
+
+// Changes made to the project from the backend need to be pulled by the frontend
+fn on_project_edited<R: Runtime>(window: &tauri::Window<R>, prjct: &Project) -> () {
+    set_edited(true);
+    if let Ok(title) = window.title() {
+        if !title.ends_with('*') {
+            window.set_title(format!("{}*", title).as_str()).unwrap();
+        }
+    }
+    window.emit("on_project_update", &prjct.scenes).unwrap();
 }
 
 /// COMMANDS
@@ -347,6 +473,34 @@ fn delete_scene<R: Runtime>(window: tauri::Window<R>, id: NanoID) -> Result<Scen
     ret
 }
 
+#[tauri::command]
+fn split_scene_by_actor(scene: Scene) -> Result<Vec<Scene>, String> {
+    scene.split_by_actor()
+}
+
+#[tauri::command]
+fn unanimated_positions(scene: Scene) -> Vec<(NanoID, usize)> {
+    scene.unanimated_positions()
+}
+
+#[tauri::command]
+fn scene_bytes(id: NanoID) -> Result<Vec<u8>, String> {
+    PROJECT.lock().unwrap().scene_bytes(&id)
+}
+
+#[tauri::command]
+fn scene_to_dot(scene: Scene) -> String {
+    scene.to_dot()
+}
+
+#[tauri::command]
+fn duplicate_stage(mut scene: Scene, id: NanoID) -> Result<Scene, String> {
+    scene
+        .duplicate_stage(&id)
+        .ok_or_else(|| format!("Invalid Stage ID: {}", id))?;
+    Ok(scene)
+}
+
 /* Stage */
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -386,18 +540,20 @@ async fn open_stage_editor<R: Runtime>(
     stage: Option<Stage>,
     control: Option<Stage>,
 ) -> () {
-    let stage = stage.unwrap_or(Stage::from_count(
-        control
-            .as_ref()
-            .and_then(|stage| Some(stage.positions.len()))
-            .unwrap_or(1),
-    ));
+    let stage = stage.unwrap_or_else(|| {
+        PROJECT.lock().unwrap().make_stage(
+            control
+                .as_ref()
+                .and_then(|stage| Some(stage.positions.len()))
+                .unwrap_or(1),
+        )
+    });
     open_stage_editor_impl(&app, EditorPayload { stage, control });
 }
 
 #[tauri::command]
 async fn open_stage_editor_from<R: Runtime>(app: tauri::AppHandle<R>, control: Stage) -> () {
-    let mut stage = Stage::from_count(control.positions.len());
+    let mut stage = PROJECT.lock().unwrap().make_stage(control.positions.len());
     stage.tags = control.tags.clone();
     let payload = EditorPayload {
         stage,
@@ -419,11 +575,174 @@ async fn stage_save_and_close<R: Runtime>(
     let _ = window.close();
 }
 
+#[tauri::command]
+fn apply_alignment_preset(mut stage: Stage, preset: AlignmentPreset) -> Stage {
+    stage.apply_alignment_preset(preset);
+    stage
+}
+
 /* Position related */
 
 #[tauri::command]
 fn make_position() -> Position {
-    Position::default()
+    PROJECT.lock().unwrap().make_position()
+}
+
+#[tauri::command]
+fn normalize_creature_sex<R: Runtime>(window: tauri::Window<R>, sex: Sex) -> usize {
+    let mut prjct = PROJECT.lock().unwrap();
+    let count = prjct.normalize_creature_sex(sex);
+    if count > 0 {
+        on_project_edited(&window, &prjct);
+    }
+    count
+}
+
+#[tauri::command]
+fn get_default_race() -> String {
+    PROJECT.lock().unwrap().default_race.clone()
+}
+
+#[tauri::command]
+fn set_default_race<R: Runtime>(window: tauri::Window<R>, race: String) -> Result<(), String> {
+    PROJECT.lock().unwrap().set_default_race(race)?;
+    set_edited(true);
+    if let Ok(title) = window.title() {
+        if !title.ends_with('*') {
+            window.set_title(format!("{}*", title).as_str()).unwrap();
+        }
+    }
+    Ok(())
+}
+
+// None disables the check of event names against the sex of their position
+#[tauri::command]
+fn set_sex_naming<R: Runtime>(window: tauri::Window<R>, naming: Option<SexNaming>) -> () {
+    let mut prjct = PROJECT.lock().unwrap();
+    prjct.sex_naming = naming;
+    on_project_edited(&window, &prjct);
+}
+
+/* Build */
+
+#[tauri::command]
+fn quick_test_export(clean_old: bool) -> Result<PathBuf, String> {
+    PROJECT
+        .lock()
+        .unwrap()
+        .quick_test_export(clean_old)
+        .map_err(|e| e.to_string())
+}
+
+// Returns the pack name the scene was exported under
+#[tauri::command]
+fn export_scene_isolated(id: NanoID, root_dir: PathBuf) -> Result<String, String> {
+    PROJECT
+        .lock()
+        .unwrap()
+        .export_scene_isolated(&id, root_dir)
+        .map(|prjct| prjct.pack_name)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn fnis_output_paths(root_dir: PathBuf) -> Vec<PathBuf> {
+    PROJECT
+        .lock()
+        .unwrap()
+        .fnis_output_paths(&root_dir, &Default::default())
+}
+
+#[tauri::command]
+fn fnis_folders(root_dir: PathBuf) -> BTreeSet<PathBuf> {
+    PROJECT
+        .lock()
+        .unwrap()
+        .fnis_folders(&root_dir, &Default::default())
+}
+
+#[tauri::command]
+fn hkx_manifest(root_dir: PathBuf) -> BTreeMap<PathBuf, BTreeSet<String>> {
+    PROJECT
+        .lock()
+        .unwrap()
+        .hkx_manifest(&root_dir, &Default::default())
+}
+
+#[tauri::command]
+fn check_hkx_present(root_dir: PathBuf) -> Vec<PathBuf> {
+    PROJECT
+        .lock()
+        .unwrap()
+        .check_hkx_present(&root_dir, &Default::default())
+}
+
+#[tauri::command]
+fn clean_export(root_dir: PathBuf) -> Result<Vec<PathBuf>, String> {
+    PROJECT
+        .lock()
+        .unwrap()
+        .clean_export(&root_dir, &Default::default())
+        .map_err(|e| e.to_string())
+}
+
+/* Integrity */
+
+#[tauri::command]
+fn find_id_collisions() -> BTreeMap<NanoID, Vec<IdKind>> {
+    let collisions = PROJECT.lock().unwrap().find_id_collisions();
+    for (id, kinds) in &collisions {
+        warn!("Id {} is used as {:?}", id, kinds);
+    }
+    collisions
+}
+
+#[tauri::command]
+fn diagnostics() -> BTreeMap<NanoID, Vec<String>> {
+    PROJECT.lock().unwrap().diagnostics()
+}
+
+#[tauri::command]
+fn self_check() -> HealthReport {
+    PROJECT.lock().unwrap().self_check()
+}
+
+#[tauri::command]
+fn compatibility_report() -> CompatReport {
+    PROJECT.lock().unwrap().compatibility_report()
+}
+
+// Ids of the scenes, ordered by scene name
+#[tauri::command]
+fn scenes_without_offsets() -> Vec<NanoID> {
+    PROJECT
+        .lock()
+        .unwrap()
+        .scenes_without_offsets()
+        .iter()
+        .map(|scene| scene.id.clone())
+        .collect()
+}
+
+#[tauri::command]
+fn changed_scenes_since_save() -> Vec<NanoID> {
+    PROJECT.lock().unwrap().changed_scenes_since_save()
+}
+
+#[tauri::command]
+fn export_scene_table(format: TableFormat) -> String {
+    PROJECT.lock().unwrap().export_scene_table(format)
+}
+
+// Races are the RaceKeys provided by the frameworks the user has installed
+#[tauri::command]
+fn unsupported_races(supported: HashSet<String>) -> Vec<(NanoID, String)> {
+    PROJECT.lock().unwrap().unsupported_races(&supported)
+}
+
+#[tauri::command]
+fn all_tags() -> BTreeMap<String, usize> {
+    PROJECT.lock().unwrap().all_tags()
 }
 
 /* CLI */
@@ -449,7 +768,11 @@ fn cli_convert(
     out_path.push(in_path.file_stem().unwrap());
     out_path.set_extension("slsb.json");
 
-    let mut project = Project::from_slal(in_path)?;
+    let mut options = ImportOptions::default();
+    if let Some(serde_json::Value::String(value)) = args.get("info").map(|arg| &arg.value) {
+        options.info_file = Some(value.clone());
+    }
+    let mut project = Project::from_slal(in_path, &options)?;
 
     let res = project.write(out_path.clone());
 
@@ -482,5 +805,11 @@ fn cli_build(
 
     let project = Project::from_file(file)?;
 
-    project.build(out_dir).map_err(|e| e.to_string())
+    let options = BuildOptions {
+        write_log: args
+            .get("log")
+            .is_some_and(|arg| arg.value.as_bool().unwrap_or_default()),
+        ..Default::default()
+    };
+    project.build(out_dir, &options).map_err(|e| e.to_string())
 }
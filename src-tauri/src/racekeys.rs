@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use crate::define::Offset;
+
 #[derive(Debug, Clone, Copy)]
 pub enum RaceKey {
     Human = 0,
@@ -57,6 +59,27 @@ pub enum RaceKey {
     Wolf,
 }
 
+// Races for which the futa flag is meaningful, used as default when validating positions
+pub const FUTA_RACES: [&str; 3] = ["Human", "Vampire Lord", "Werewolf"];
+
+// Rough starting alignment for a creature relative to its partner, humans are not offset
+pub fn default_offset_for(race: &str) -> Offset {
+    let (x, y, z) = match race {
+        "Canine" | "Dog" | "Wolf" | "Fox" => (0.0, -35.0, 0.0),
+        "Horse" | "Deer" | "Cow" | "Goat" => (0.0, -60.0, 0.0),
+        "Bear" | "Sabrecat" | "Troll" | "Werewolf" | "Vampire Lord" | "Gargoyle" => {
+            (0.0, -45.0, 0.0)
+        }
+        "Giant" | "Mammoth" | "Dragon" | "Dwarven Centurion" | "Lurker" => (0.0, -90.0, 0.0),
+        "Skeever" | "Chicken" | "Rabbit" | "Mudcrab" | "Spider" | "Dwarven Spider" => {
+            (0.0, -20.0, 0.0)
+        }
+        "Human" => (0.0, 0.0, 0.0),
+        _ => (0.0, -30.0, 0.0),
+    };
+    Offset { x, y, z, r: 0.0 }
+}
+
 pub fn map_legacy_to_racekey(legacykey: &str) -> Result<String, String> {
     let key = legacykey.to_lowercase();
     match key.as_str() {
@@ -117,6 +140,20 @@ pub fn map_legacy_to_racekey(legacykey: &str) -> Result<String, String> {
     }
 }
 
+// Race names which have been renamed since they were first introduced
+pub fn map_deprecated_race(race: &str) -> Option<&'static str> {
+    match race {
+        "Frostbite Spider" => Some("Spider"),
+        "Large Frostbite Spider" => Some("Large Spider"),
+        "Giant Frostbite Spider" => Some("Giant Spider"),
+        "Hare" => Some("Rabbit"),
+        "Dwarven Steam Centurion" => Some("Dwarven Centurion"),
+        "Dwarven Ballista Centurion" => Some("Dwarven Ballista"),
+        "Dwarven Sphere Centurion" => Some("Dwarven Sphere"),
+        _ => None,
+    }
+}
+
 fn get_race_map() -> HashMap<String, RaceKey> {
     HashMap::from([
         ("Human".into(), RaceKey::Human),
@@ -184,6 +221,15 @@ pub fn get_race_keys_string() -> Vec<String> {
     ret
 }
 
+// Inverse of get_race_key_bytes, if several names share a key the first one in alphabetical order is returned
+pub fn get_race_key_from_byte(byte: u8) -> Option<String> {
+    get_race_map()
+        .into_iter()
+        .filter(|(_, value)| *value as u8 == byte)
+        .map(|(key, _)| key)
+        .min()
+}
+
 pub fn get_race_key_bytes(race: &str) -> Option<u8> {
     let map = get_race_map();
     // let mut key = race.to_lowercase();
@@ -194,3 +240,16 @@ pub fn get_race_key_bytes(race: &str) -> Option<u8> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creatures_get_a_starting_offset() {
+        let wolf = default_offset_for("Wolf");
+        assert!(wolf.differs(&Offset::default()));
+        let human = default_offset_for("Human");
+        assert!(!human.differs(&Offset::default()));
+    }
+}
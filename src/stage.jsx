@@ -147,6 +147,7 @@ function Editor({ _id, _name, _positions, _tags, _extra, _control }) {
       positions: position_arg,
       tags,
       extra: {
+        ..._extra,
         fixed_len: fixedLen || 0.0,
         nav_text: navText || '',
       },